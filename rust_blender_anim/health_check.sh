@@ -46,7 +46,7 @@ fi
 
 # Check 5: Test run (dry run - just generate script)
 echo "✓ Testing script generation..."
-if cargo run --quiet -- --generate-only 2>&1 | grep -q "Python script generated successfully"; then
+if cargo run --quiet -- generate 2>&1 | grep -q "Python script generated successfully"; then
     echo "  ✅ Script generation works"
     if [ -f "setup_scene.py" ]; then
         SCRIPT_SIZE=$(wc -c < setup_scene.py)
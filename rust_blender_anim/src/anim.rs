@@ -0,0 +1,437 @@
+//! Animation data handed to the Blender setup script: static object
+//! properties plus sparse F-curve channels.
+
+use serde::Serialize;
+
+use crate::curve::{CurveKey, FCurve, Interpolation};
+use crate::math::{EulerOrder, Mat4, Quat};
+use crate::scene::{Color, Keyframe, Object, TransformError, TransformGraph, Vector3};
+
+const BASE_COLOR: &str = r#"nodes["Principled BSDF"].inputs["Base Color"].default_value"#;
+const EMISSION_COLOR: &str = r#"nodes["Principled BSDF"].inputs["Emission"].default_value"#;
+const EMISSION_STRENGTH: &str = r#"nodes["Principled BSDF"].inputs["Emission Strength"].default_value"#;
+
+/// Largest difference from the first sample of a channel that still counts
+/// as unchanged, so rounding noise doesn't key a static channel.
+const UNCHANGED: f32 = 1e-6;
+
+/// Per-object static properties and animation channels.
+///
+/// `scale`, `color` and `emission` are the frame 0 values used to build the
+/// mesh and material; the channels animate them. Since the static scale is
+/// baked into the mesh, the `scale` channel holds the ratio to it. The mesh
+/// is also offset so the object's origin sits on its `pivot`.
+#[derive(Serialize)]
+pub struct ObjAnimData {
+    pub name: String,
+    pub object_type: String,
+    pub rotation_order: EulerOrder,
+    pub scale: [f32; 3],
+    pub pivot: [f32; 3],
+    pub color: [f32; 4],
+    pub emission: f32,
+    pub parent: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+/// One animated property component, mirroring a Blender F-curve.
+#[derive(Serialize)]
+pub struct Channel {
+    pub target: ChannelTarget,
+    pub data_path: String,
+    pub index: usize,
+    pub keys: FCurve,
+}
+
+/// The datablock a channel's `data_path` is relative to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelTarget {
+    Object,
+    /// The node tree of the object's generated material.
+    Material,
+}
+
+/// The animated state of an object at one frame, evaluated from its channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjState {
+    pub location: Vector3,
+    pub rotation: Vector3,
+    pub rotation_order: EulerOrder,
+    /// Scale relative to the static `scale` baked into the mesh.
+    pub scale: Vector3,
+    pub color: Color,
+    pub emission: f32,
+    pub visible: bool,
+}
+
+impl ObjState {
+    /// Transform relative to the parent, with the static scale left out.
+    pub fn local_matrix(&self) -> Mat4 {
+        Mat4::from_trs(self.location, Quat::from_euler(self.rotation, self.rotation_order), self.scale)
+    }
+}
+
+impl ObjAnimData {
+    pub fn channel(&self, target: ChannelTarget, data_path: &str, index: usize) -> Option<&Channel> {
+        self.channels.iter().find(|c| c.target == target && c.data_path == data_path && c.index == index)
+    }
+
+    /// Evaluates the channels at `frame`. Properties without a channel keep
+    /// their static value.
+    pub fn state_at(&self, frame: f32) -> ObjState {
+        let value = |target, path, index, default: f32| {
+            self.channel(target, path, index).map_or(default, |c| c.keys.evaluate(frame))
+        };
+        let vector = |path, default: f32| {
+            Vector3::new(
+                value(ChannelTarget::Object, path, 0, default),
+                value(ChannelTarget::Object, path, 1, default),
+                value(ChannelTarget::Object, path, 2, default),
+            )
+        };
+        let [r, g, b, a] = self.color;
+
+        ObjState {
+            location: vector("location", 0.0),
+            rotation: vector("rotation_euler", 0.0),
+            rotation_order: self.rotation_order,
+            scale: vector("scale", 1.0),
+            color: Color::new(
+                value(ChannelTarget::Material, BASE_COLOR, 0, r),
+                value(ChannelTarget::Material, BASE_COLOR, 1, g),
+                value(ChannelTarget::Material, BASE_COLOR, 2, b),
+                value(ChannelTarget::Material, BASE_COLOR, 3, a),
+            ),
+            emission: value(ChannelTarget::Material, EMISSION_STRENGTH, 0, self.emission),
+            visible: value(ChannelTarget::Object, "hide_render", 0, 0.0) < 0.5,
+        }
+    }
+
+    /// Static properties of `obj`, without any channels.
+    pub fn from_object(obj: &Object) -> Self {
+        Self {
+            name: obj.name.clone(),
+            object_type: obj.object_type.clone(),
+            rotation_order: obj.rotation_order,
+            scale: [obj.scale.x, obj.scale.y, obj.scale.z],
+            pivot: [obj.pivot.x, obj.pivot.y, obj.pivot.z],
+            color: [obj.color.r, obj.color.g, obj.color.b, obj.color.a],
+            emission: obj.emission,
+            parent: obj.parent.clone(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Channels built directly from the sparse `obj.keyframes`.
+    pub fn keyed(obj: &Object) -> Self {
+        let mut data = Self::from_object(obj);
+        let keyframes = &obj.keyframes;
+        let base = data.scale;
+
+        data.push_keyed(ChannelTarget::Object, "location", keyframes, |k| k.location.map(|v| vec![v.x, v.y, v.z]));
+        data.push_keyed(ChannelTarget::Object, "rotation_euler", keyframes, |k| {
+            k.rotation.map(|v| vec![v.x, v.y, v.z])
+        });
+        data.push_keyed(ChannelTarget::Object, "scale", keyframes, |k| {
+            k.scale.map(|v| vec![ratio(v.x, base[0]), ratio(v.y, base[1]), ratio(v.z, base[2])])
+        });
+        for path in [BASE_COLOR, EMISSION_COLOR] {
+            data.push_keyed(ChannelTarget::Material, path, keyframes, |k| k.color.map(|c| vec![c.r, c.g, c.b, c.a]));
+        }
+        data.push_keyed(ChannelTarget::Material, EMISSION_STRENGTH, keyframes, |k| k.emission.map(|e| vec![e]));
+
+        // Visibility can't be interpolated
+        let hidden: Vec<Keyframe> =
+            keyframes.iter().map(|k| Keyframe { interpolation: Interpolation::Constant, ..k.clone() }).collect();
+        for path in ["hide_render", "hide_viewport"] {
+            data.push_keyed(ChannelTarget::Object, path, &hidden, |k| k.visible.map(|v| vec![hidden_value(v)]));
+        }
+
+        data
+    }
+
+    fn push_keyed(
+        &mut self,
+        target: ChannelTarget,
+        data_path: &str,
+        keyframes: &[Keyframe],
+        value: impl Fn(&Keyframe) -> Option<Vec<f32>>,
+    ) {
+        let keyed: Vec<(&Keyframe, Vec<f32>)> = keyframes.iter().filter_map(|k| value(k).map(|v| (k, v))).collect();
+        let Some((_, first)) = keyed.first() else {
+            return;
+        };
+
+        for index in 0..first.len() {
+            let keys = keyed.iter().map(|(k, v)| CurveKey::new(k.frame as f32, v[index], k.interpolation)).collect();
+            let mut curve = FCurve::new(keys);
+            for (key, (keyframe, _)) in curve.keys.iter_mut().zip(&keyed) {
+                if let Some(handles) = keyframe.handles {
+                    key.handle_left = [key.frame + handles.left[0], key.value + handles.left[1]];
+                    key.handle_right = [key.frame + handles.right[0], key.value + handles.right[1]];
+                }
+            }
+            self.channels.push(Channel { target, data_path: data_path.to_string(), index, keys: curve });
+        }
+    }
+}
+
+/// The scene camera: a keyed location, aimed at the `target` object.
+#[derive(Serialize)]
+pub struct CameraAnimData {
+    pub target: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+impl CameraAnimData {
+    /// Fits per-frame camera positions (starting at frame 0) within `tolerance`.
+    pub fn sampled(target: Option<String>, locations: &[[f32; 3]], tolerance: f32) -> Self {
+        let channels = split(locations)
+            .into_iter()
+            .enumerate()
+            .map(|(index, samples)| Channel {
+                target: ChannelTarget::Object,
+                data_path: "location".to_string(),
+                index,
+                keys: FCurve::fit(&samples, 0.0, tolerance),
+            })
+            .collect();
+        Self { target, channels }
+    }
+
+    pub fn location_at(&self, frame: f32) -> Vector3 {
+        let mut location = [0.0; 3];
+        for channel in self.channels.iter().filter(|c| c.data_path == "location") {
+            location[channel.index] = channel.keys.evaluate(frame);
+        }
+        Vector3::new(location[0], location[1], location[2])
+    }
+}
+
+/// A bone of the armature rig, in the rest pose (frame 0). Every bone points
+/// along +Y with no roll, so its axes match its object's parent space and
+/// the pose keys are the object keys less the `rest` location.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Bone {
+    pub name: String,
+    pub parent: Option<String>,
+    /// The object's location at frame 0, relative to its parent.
+    pub rest: [f32; 3],
+    /// Position in armature space: the rest locations summed down the chain.
+    pub head: [f32; 3],
+}
+
+/// One bone per object of `anim_data`, with the same hierarchy.
+pub fn armature(anim_data: &[ObjAnimData]) -> Result<Vec<Bone>, TransformError> {
+    let graph = TransformGraph::new(anim_data.iter().map(|d| (d.name.as_str(), d.parent.as_deref())))?;
+    let rest: Vec<Vector3> = anim_data.iter().map(|d| d.state_at(0.0).location).collect();
+    Ok(anim_data
+        .iter()
+        .enumerate()
+        .map(|(i, data)| {
+            let mut head = rest[i];
+            let mut node = graph.parent(i);
+            while let Some(parent) = node {
+                head = head + rest[parent];
+                node = graph.parent(parent);
+            }
+            Bone {
+                name: data.name.clone(),
+                parent: data.parent.clone(),
+                rest: [rest[i].x, rest[i].y, rest[i].z],
+                head: [head.x, head.y, head.z],
+            }
+        })
+        .collect())
+}
+
+/// Dense per-frame samples of one object, reduced to sparse channels by
+/// [`Track::fit`].
+#[derive(Default)]
+pub struct Track {
+    locations: Vec<[f32; 3]>,
+    rotations: Vec<[f32; 3]>,
+    scales: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    emissions: Vec<f32>,
+    visible: Vec<bool>,
+}
+
+impl Track {
+    /// Records the state of `obj` for the next frame.
+    pub fn push(&mut self, obj: &Object) {
+        self.locations.push([obj.location.x, obj.location.y, obj.location.z]);
+        self.rotations.push([obj.rotation.x, obj.rotation.y, obj.rotation.z]);
+        self.scales.push([obj.scale.x, obj.scale.y, obj.scale.z]);
+        self.colors.push([obj.color.r, obj.color.g, obj.color.b, obj.color.a]);
+        self.emissions.push(obj.emission);
+        self.visible.push(obj.visible);
+    }
+
+    /// The rotation recorded for the previous frame.
+    pub fn last_rotation(&self) -> Option<Vector3> {
+        self.rotations.last().map(|r| Vector3::new(r[0], r[1], r[2]))
+    }
+
+    /// Fits the samples (starting at frame 0) to Bezier channels within
+    /// `tolerance`. Location and rotation are always keyed, the other
+    /// channels only when they change over time.
+    pub fn fit(self, obj: &Object, tolerance: f32) -> ObjAnimData {
+        let mut data = ObjAnimData::from_object(obj);
+        let base = data.scale;
+        let scales: Vec<[f32; 3]> =
+            self.scales.iter().map(|s| [ratio(s[0], base[0]), ratio(s[1], base[1]), ratio(s[2], base[2])]).collect();
+        let hidden: Vec<f32> = self.visible.iter().map(|&v| hidden_value(v)).collect();
+
+        let mut fit = |target, data_path: &str, components: Vec<Vec<f32>>, always: bool, constant: bool| {
+            for (index, samples) in components.into_iter().enumerate() {
+                if !always && samples.iter().all(|&v| (v - samples[0]).abs() <= UNCHANGED) {
+                    continue;
+                }
+                let keys = if constant {
+                    FCurve::fit_constant(&samples, 0.0, tolerance)
+                } else {
+                    FCurve::fit(&samples, 0.0, tolerance)
+                };
+                data.channels.push(Channel { target, data_path: data_path.to_string(), index, keys });
+            }
+        };
+
+        fit(ChannelTarget::Object, "location", split(&self.locations), true, false);
+        fit(ChannelTarget::Object, "rotation_euler", split(&self.rotations), true, false);
+        fit(ChannelTarget::Object, "scale", split(&scales), false, false);
+        fit(ChannelTarget::Material, BASE_COLOR, split(&self.colors), false, false);
+        fit(ChannelTarget::Material, EMISSION_COLOR, split(&self.colors), false, false);
+        fit(ChannelTarget::Material, EMISSION_STRENGTH, vec![self.emissions], false, false);
+        fit(ChannelTarget::Object, "hide_render", vec![hidden.clone()], false, true);
+        fit(ChannelTarget::Object, "hide_viewport", vec![hidden], false, true);
+
+        data
+    }
+}
+
+/// Transposes per-frame vectors into one sample list per component.
+fn split<const N: usize>(values: &[[f32; N]]) -> Vec<Vec<f32>> {
+    (0..N).map(|i| values.iter().map(|v| v[i]).collect()).collect()
+}
+
+fn ratio(value: f32, base: f32) -> f32 {
+    if base != 0.0 {
+        value / base
+    } else {
+        1.0
+    }
+}
+
+fn hidden_value(visible: bool) -> f32 {
+    if visible {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::KeyHandles;
+
+    fn object(keyframes: Vec<Keyframe>) -> Object {
+        Object {
+            name: "Keyed".to_string(),
+            object_type: "CUBE".to_string(),
+            location: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+            rotation_order: EulerOrder::Xyz,
+            scale: Vector3::new(2.0, 2.0, 2.0),
+            pivot: Vector3::default(),
+            color: Color::new(1.0, 1.0, 1.0, 1.0),
+            emission: 0.0,
+            visible: true,
+            parent: None,
+            keyframes,
+        }
+    }
+
+    fn channel<'a>(data: &'a ObjAnimData, data_path: &str, index: usize) -> &'a FCurve {
+        &data.channels.iter().find(|c| c.data_path == data_path && c.index == index).unwrap().keys
+    }
+
+    #[test]
+    fn keyed_objects_keep_their_keys_and_interpolation() {
+        let keyframes = vec![
+            Keyframe {
+                frame: 0,
+                interpolation: Interpolation::Constant,
+                location: Some(Vector3::new(1.0, 2.0, 3.0)),
+                visible: Some(true),
+                ..Keyframe::default()
+            },
+            Keyframe {
+                frame: 10,
+                interpolation: Interpolation::Linear,
+                location: Some(Vector3::new(4.0, 2.0, 3.0)),
+                scale: Some(Vector3::new(4.0, 2.0, 1.0)),
+                ..Keyframe::default()
+            },
+            Keyframe {
+                frame: 20,
+                location: Some(Vector3::new(6.0, 2.0, 3.0)),
+                emission: Some(5.0),
+                visible: Some(false),
+                ..Keyframe::default()
+            },
+        ];
+        let data = ObjAnimData::keyed(&object(keyframes));
+
+        let x = channel(&data, "location", 0);
+        let frames: Vec<(f32, f32, Interpolation)> =
+            x.keys.iter().map(|k| (k.frame, k.value, k.interpolation)).collect();
+        assert_eq!(
+            frames,
+            [
+                (0.0, 1.0, Interpolation::Constant),
+                (10.0, 4.0, Interpolation::Linear),
+                (20.0, 6.0, Interpolation::Bezier)
+            ]
+        );
+        // Held up to the next key, then a straight line
+        assert_eq!(x.evaluate(9.9), 1.0);
+        assert!((x.evaluate(15.0) - 5.0).abs() < 1e-5);
+        assert_eq!(channel(&data, "location", 1).evaluate(15.0), 2.0);
+
+        // Only the keys that set a value make it into its channel, and the
+        // scale is relative to the mesh's
+        let sx = channel(&data, "scale", 0);
+        assert_eq!(sx.keys.len(), 1);
+        assert_eq!(sx.keys[0].value, 2.0);
+        assert_eq!(channel(&data, "scale", 2).keys[0].value, 0.5);
+        assert_eq!(channel(&data, EMISSION_STRENGTH, 0).keys[0].frame, 20.0);
+
+        let hidden = channel(&data, "hide_render", 0);
+        assert!(hidden.keys.iter().all(|k| k.interpolation == Interpolation::Constant));
+        assert_eq!((hidden.evaluate(19.0), hidden.evaluate(20.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn keyed_handles_override_the_automatic_ones() {
+        let key = |frame, x, handles| Keyframe {
+            frame,
+            location: Some(Vector3::new(x, 0.0, 0.0)),
+            handles,
+            ..Keyframe::default()
+        };
+        let ease = KeyHandles { left: [-3.0, 0.0], right: [3.0, 0.0] };
+        let data = ObjAnimData::keyed(&object(vec![key(0, 0.0, None), key(10, 5.0, Some(ease)), key(20, 0.0, None)]));
+
+        let x = channel(&data, "location", 0);
+        assert_eq!((x.keys[1].handle_left, x.keys[1].handle_right), ([7.0, 5.0], [13.0, 5.0]));
+        // The others keep their automatic handles
+        assert_eq!(x.keys[0].handle_right, [10.0 / 3.0, 0.0]);
+        // The serialized keys carry the handles to Blender
+        let json = serde_json::to_value(x).unwrap();
+        assert_eq!(json["handle_left"].as_array().unwrap()[2..4], [7.0, 5.0]);
+        assert_eq!(json["handle_right"].as_array().unwrap()[2..4], [13.0, 5.0]);
+    }
+}
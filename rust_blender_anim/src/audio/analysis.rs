@@ -0,0 +1,325 @@
+//! Onset, tempo and beat detection, and band energy envelopes.
+//!
+//! The track is cut into overlapping windows (a short-time Fourier
+//! transform). The onset strength is the spectral flux between windows: how
+//! much louder each frequency got. Onsets are its peaks; the tempo is the
+//! lag where it best correlates with itself; and the beats are tracked by
+//! dynamic programming, picking the onsets that best fit that tempo (Ellis,
+//! "Beat Tracking by Dynamic Programming", 2007).
+
+use std::path::Path;
+
+use super::fft;
+use super::wav::Wav;
+
+/// Samples per analysis window.
+pub const WINDOW: usize = 1024;
+/// Samples between consecutive windows.
+pub const HOP: usize = 512;
+/// Frequency ranges of [`Analysis::bands`], in Hz: bass, low mids, high
+/// mids and treble.
+pub const BANDS: [(f32, f32); 4] = [(20.0, 150.0), (150.0, 500.0), (500.0, 4000.0), (4000.0, 16000.0)];
+/// Index of the bass band in [`BANDS`].
+pub const BASS: usize = 0;
+
+/// Tempo range searched, in BPM.
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+/// Tempo the estimate is biased towards, to settle between multiples.
+const PREFERRED_BPM: f64 = 120.0;
+/// How strictly the beat tracker keeps to the tempo.
+const TIGHTNESS: f32 = 100.0;
+/// How far above the local average an onset must rise, on the normalized
+/// onset strength.
+const ONSET_THRESHOLD: f32 = 0.07;
+
+/// What the music is doing over one animation frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameFeatures {
+    /// Beats since the first detected beat, fractional.
+    pub beat: f32,
+    /// Peak onset strength, from 0 to 1.
+    pub onset: f32,
+    /// Mean energy of each of [`BANDS`], from 0 to 1.
+    pub bands: [f32; BANDS.len()],
+}
+
+/// A track's detected rhythm and energy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub sample_rate: u32,
+    /// Onset strength per hop, from 0 to 1.
+    pub onset_envelope: Vec<f32>,
+    /// Energy of each of [`BANDS`] per hop, each from 0 to 1.
+    pub bands: Vec<[f32; BANDS.len()]>,
+    /// Onset times in seconds.
+    pub onsets: Vec<f64>,
+    /// Estimated tempo.
+    pub bpm: f64,
+    /// Beat times in seconds.
+    pub beats: Vec<f64>,
+}
+
+impl Analysis {
+    /// Decodes the WAV file at `path` and analyzes it, see [`Analysis::new`].
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let wav = Wav::read(path)?;
+        Ok(Self::new(&wav.mono(), wav.sample_rate))
+    }
+
+    /// Analyzes mono `samples`.
+    pub fn new(samples: &[f32], sample_rate: u32) -> Self {
+        let window = fft::hann(WINDOW);
+        let bin_hz = sample_rate as f32 / WINDOW as f32;
+        let band_bins: Vec<(usize, usize)> = BANDS
+            .iter()
+            .map(|&(low, high)| ((low / bin_hz).ceil() as usize, ((high / bin_hz) as usize).min(WINDOW / 2)))
+            .collect();
+
+        // Windows centered on every hop, zero-padded at the ends
+        let hops = samples.len().div_ceil(HOP);
+        let mut frame = vec![0.0; WINDOW];
+        // Silence before the start, so a hit on the first sample is an onset
+        let mut previous = vec![0.0; WINDOW / 2 + 1];
+        let mut flux = Vec::with_capacity(hops);
+        let mut bands = Vec::with_capacity(hops);
+        for hop in 0..hops {
+            let start = (hop * HOP) as isize - (WINDOW / 2) as isize;
+            for (i, sample) in frame.iter_mut().enumerate() {
+                let at = start + i as isize;
+                *sample = if at >= 0 { samples.get(at as usize).copied().unwrap_or(0.0) } else { 0.0 };
+            }
+            let magnitudes = fft::magnitudes(&frame, &window);
+
+            let mut energy = [0.0; BANDS.len()];
+            for (e, &(low, high)) in energy.iter_mut().zip(&band_bins) {
+                let power: f32 = magnitudes.get(low..=high).unwrap_or(&[]).iter().map(|m| m * m).sum();
+                *e = power.sqrt();
+            }
+            bands.push(energy);
+
+            // Log-compressed, so quiet parts count too
+            let compressed: Vec<f32> = magnitudes.iter().map(|m| (1.0 + 100.0 * m).ln()).collect();
+            flux.push(compressed.iter().zip(&previous).map(|(m, p)| (m - p).max(0.0)).sum());
+            previous = compressed;
+        }
+
+        let peak = flux.iter().copied().fold(0.0, f32::max);
+        if peak > 0.0 {
+            flux.iter_mut().for_each(|v| *v /= peak);
+        }
+        // Scaled by a loud but typical level rather than the peak, which
+        // may be a single click
+        for band in 0..BANDS.len() {
+            let mut levels: Vec<f32> = bands.iter().map(|e| e[band]).collect();
+            levels.sort_by(f32::total_cmp);
+            let loud = levels.get(levels.len() * 95 / 100).copied().unwrap_or(0.0);
+            if loud > 0.0 {
+                bands.iter_mut().for_each(|e| e[band] = (e[band] / loud).min(1.0));
+            }
+        }
+
+        let hop_secs = HOP as f64 / f64::from(sample_rate);
+        let period = tempo_period(&flux, hop_secs);
+        let onsets = pick_onsets(&flux).into_iter().map(|i| i as f64 * hop_secs).collect();
+        let beats = track_beats(&flux, period).into_iter().map(|i| i as f64 * hop_secs).collect();
+        Self { sample_rate, onset_envelope: flux, bands, onsets, bpm: 60.0 / (period * hop_secs), beats }
+    }
+
+    /// Beats since the first detected beat at `secs`, counting evenly
+    /// between beats and extrapolating before the first and after the last.
+    pub fn beat_at(&self, secs: f64) -> f64 {
+        match self.beats[..] {
+            [] => secs * self.bpm / 60.0,
+            [only] => (secs - only) * self.bpm / 60.0,
+            _ => {
+                let i = self.beats.partition_point(|&b| b <= secs).clamp(1, self.beats.len() - 1) - 1;
+                let (a, b) = (self.beats[i], self.beats[i + 1]);
+                i as f64 + (secs - a) / (b - a)
+            }
+        }
+    }
+
+    /// Features of frames `0..=frames` at `fps`. Frames past the end of
+    /// the track keep counting beats, with no onsets or energy.
+    pub fn frames(&self, frames: i32, fps: u32) -> Vec<FrameFeatures> {
+        let hops_per_sec = f64::from(self.sample_rate) / HOP as f64;
+        (0..=frames.max(0))
+            .map(|frame| {
+                let secs = f64::from(frame) / f64::from(fps);
+                let first = (secs * hops_per_sec).round() as usize;
+                let last = (((secs + 1.0 / f64::from(fps)) * hops_per_sec).round() as usize).max(first + 1);
+                let range = first.min(self.bands.len())..last.min(self.bands.len());
+
+                let mut bands = [0.0; BANDS.len()];
+                if !range.is_empty() {
+                    for e in &self.bands[range.clone()] {
+                        bands.iter_mut().zip(e).for_each(|(b, e)| *b += e);
+                    }
+                    bands.iter_mut().for_each(|b| *b /= range.len() as f32);
+                }
+                FrameFeatures {
+                    beat: self.beat_at(secs) as f32,
+                    onset: self.onset_envelope[range].iter().copied().fold(0.0, f32::max),
+                    bands,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Hops that are a local maximum of the onset strength and stand out from
+/// their surroundings, at least 3 hops apart.
+fn pick_onsets(flux: &[f32]) -> Vec<usize> {
+    let mut onsets: Vec<usize> = Vec::new();
+    for (i, &value) in flux.iter().enumerate() {
+        let around = |radius: usize| &flux[i.saturating_sub(radius)..(i + radius + 1).min(flux.len())];
+        let peak = around(3).iter().all(|&v| v <= value);
+        let local = around(16);
+        let mean = local.iter().sum::<f32>() / local.len() as f32;
+        if peak && value >= mean + ONSET_THRESHOLD && onsets.last().is_none_or(|&last| i - last > 3) {
+            onsets.push(i);
+        }
+    }
+    onsets
+}
+
+/// The beat period in hops, from the autocorrelation of the onset strength.
+fn tempo_period(flux: &[f32], hop_secs: f64) -> f64 {
+    let lag = |bpm: f64| 60.0 / (bpm * hop_secs);
+    let preferred = lag(PREFERRED_BPM);
+    let (min_lag, max_lag) = (lag(MAX_BPM).floor() as usize, lag(MIN_BPM).ceil() as usize);
+    if flux.len() <= max_lag + 1 {
+        return preferred;
+    }
+
+    let mean = flux.iter().sum::<f32>() / flux.len() as f32;
+    let centered: Vec<f32> = flux.iter().map(|v| v - mean).collect();
+    let autocorrelation: Vec<f64> = (0..=max_lag + 1)
+        .map(|lag| {
+            let sum: f64 = centered.iter().zip(&centered[lag..]).map(|(a, b)| f64::from(a * b)).sum();
+            sum / (flux.len() - lag) as f64
+        })
+        .collect();
+    // Weighted towards the preferred tempo, one octave either side
+    let weighted = |lag: usize| {
+        let octaves = (lag as f64 / preferred).log2();
+        autocorrelation[lag] * (-0.5 * octaves * octaves).exp()
+    };
+    let best = (min_lag.max(1)..=max_lag).max_by(|&a, &b| weighted(a).total_cmp(&weighted(b))).unwrap();
+    if autocorrelation[best] <= 0.0 {
+        return preferred;
+    }
+
+    // Refine between hops with a parabola through the neighbors
+    let (a, b, c) = (autocorrelation[best - 1], autocorrelation[best], autocorrelation[best + 1]);
+    let denominator = a - 2.0 * b + c;
+    let offset = if denominator < 0.0 { (0.5 * (a - c) / denominator).clamp(-0.5, 0.5) } else { 0.0 };
+    best as f64 + offset
+}
+
+/// Hops of the beats, a sequence of strong onsets spaced close to `period`.
+fn track_beats(flux: &[f32], period: f64) -> Vec<usize> {
+    let (min_gap, max_gap) = ((period * 0.5).round().max(1.0) as usize, (period * 2.0).round() as usize);
+    let mut score = vec![0.0f32; flux.len()];
+    let mut back: Vec<Option<usize>> = vec![None; flux.len()];
+    for i in 0..flux.len() {
+        // The best previous beat, penalized for straying from the period
+        let best = (i.saturating_sub(max_gap)..=i.saturating_sub(min_gap))
+            .filter(|&prev| prev + min_gap <= i)
+            .map(|prev| {
+                let stretch = ((i - prev) as f64 / period).ln() as f32;
+                (prev, score[prev] - TIGHTNESS * stretch * stretch)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        score[i] = flux[i] + best.map_or(0.0, |(_, s)| s.max(0.0));
+        back[i] = best.filter(|&(_, s)| s > 0.0).map(|(prev, _)| prev);
+    }
+
+    // Start from the best-scoring beat within the last period and walk back
+    let tail = flux.len().saturating_sub(period.round() as usize);
+    let Some(mut beat) = (tail..flux.len()).max_by(|&a, &b| score[a].total_cmp(&score[b])) else {
+        return Vec::new();
+    };
+    let mut beats = vec![beat];
+    while let Some(prev) = back[beat] {
+        beats.push(prev);
+        beat = prev;
+    }
+    beats.reverse();
+    beats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 22050;
+
+    /// Short decaying noise bursts every beat at `bpm`, starting at
+    /// `offset` seconds.
+    fn click_track(bpm: f64, offset: f64, secs: f64) -> (Vec<f32>, Vec<f64>) {
+        let period = 60.0 / bpm;
+        let clicks: Vec<f64> = (0..).map(|i| offset + i as f64 * period).take_while(|&t| t < secs).collect();
+        let mut samples = vec![0.0; (secs * f64::from(RATE)) as usize];
+        let mut noise = 1u32;
+        for &click in &clicks {
+            let start = (click * f64::from(RATE)) as usize;
+            for (i, sample) in samples[start..].iter_mut().take(400).enumerate() {
+                noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let white = (noise >> 8) as f32 / (1 << 23) as f32 - 1.0;
+                *sample = white * (-(i as f32) / 60.0).exp();
+            }
+        }
+        (samples, clicks)
+    }
+
+    #[test]
+    fn a_click_track_gives_its_tempo_and_beats() {
+        for bpm in [90.0, 128.0, 150.0] {
+            let (samples, clicks) = click_track(bpm, 0.25, 12.0);
+            let analysis = Analysis::new(&samples, RATE);
+            assert!((analysis.bpm - bpm).abs() < 1.5, "{} BPM detected as {}", bpm, analysis.bpm);
+
+            // Within a hop of the clicks, and none missed past the first few
+            let hop_secs = HOP as f64 / f64::from(RATE);
+            for beat in &analysis.beats {
+                let nearest = clicks.iter().map(|c| (c - beat).abs()).fold(f64::MAX, f64::min);
+                assert!(nearest <= hop_secs, "{} BPM: beat at {} s is {} s off", bpm, beat, nearest);
+            }
+            assert!(
+                analysis.beats.len() + 2 >= clicks.len(),
+                "{} BPM: {} beats for {} clicks",
+                bpm,
+                analysis.beats.len(),
+                clicks.len()
+            );
+            assert_eq!(analysis.onsets.len(), clicks.len(), "{} BPM", bpm);
+        }
+    }
+
+    #[test]
+    fn frames_count_beats_between_the_detected_ones() {
+        let (samples, _) = click_track(120.0, 0.0, 8.0);
+        let analysis = Analysis::new(&samples, RATE);
+        let frames = analysis.frames(240, 30);
+        assert_eq!(frames.len(), 241);
+        // Two beats a second, from the first click
+        for (frame, features) in frames.iter().enumerate().step_by(15) {
+            let expected = frame as f32 / 15.0 - analysis.beats[0] as f32 * 2.0;
+            assert!((features.beat - expected).abs() < 0.1, "frame {}: beat {}", frame, features.beat);
+        }
+        // The clicks are broadband noise, peaking right on the beat
+        assert!(frames[0].onset > 0.5 && frames[7].onset < 0.2);
+        assert!(frames[0].bands.iter().all(|&e| e > 0.5), "{:?}", frames[0].bands);
+        // Past the end of the track, still counting with no energy
+        assert_eq!(analysis.frames(300, 30)[300].bands, [0.0; BANDS.len()]);
+    }
+
+    #[test]
+    fn silence_has_no_onsets() {
+        let analysis = Analysis::new(&vec![0.0; RATE as usize * 4], RATE);
+        assert!(analysis.onsets.is_empty());
+        assert_eq!(analysis.bpm.round(), PREFERRED_BPM);
+    }
+}
@@ -0,0 +1,632 @@
+//! The master bus: effects that turn the synth's instruments into a
+//! finished mix.
+//!
+//! The kick stays dry and keys a compressor that ducks everything else, the
+//! "music", under it. The music gets a tempo-synced echo and a Freeverb-style
+//! reverb first. A true-peak limiter keeps the sum below the ceiling,
+//! including the peaks between samples that a DAC or a lossy encoder would
+//! reconstruct.
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use crate::tempo::TempoMap;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EffectsConfig {
+    pub delay: DelayConfig,
+    pub reverb: ReverbConfig,
+    pub compressor: CompressorConfig,
+    pub limiter: LimiterConfig,
+}
+
+/// An echo of the music, in time with the tempo.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DelayConfig {
+    pub enabled: bool,
+    /// Time between echoes, in beats.
+    pub beats: f64,
+    /// Level of each echo relative to the one before, from 0 to 1.
+    pub feedback: f32,
+    /// Level of the echoes in the mix.
+    pub mix: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReverbConfig {
+    pub enabled: bool,
+    /// From 0 to 1, how long the reverb rings.
+    pub room_size: f32,
+    /// From 0 to 1, how quickly the highs die away.
+    pub damping: f32,
+    pub mix: f32,
+}
+
+/// Ducks the music whenever the kick hits.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressorConfig {
+    pub enabled: bool,
+    /// Kick level above which the music is turned down.
+    pub threshold_db: f32,
+    /// How many dB over the threshold make one dB more.
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimiterConfig {
+    pub enabled: bool,
+    /// Highest true peak of the mix.
+    pub ceiling_db: f32,
+    pub release_ms: f32,
+}
+
+impl Default for DelayConfig {
+    fn default() -> Self {
+        Self { enabled: true, beats: 0.75, feedback: 0.35, mix: 0.15 }
+    }
+}
+
+impl Default for ReverbConfig {
+    fn default() -> Self {
+        Self { enabled: true, room_size: 0.7, damping: 0.5, mix: 0.12 }
+    }
+}
+
+impl Default for CompressorConfig {
+    fn default() -> Self {
+        Self { enabled: true, threshold_db: -18.0, ratio: 4.0, attack_ms: 2.0, release_ms: 150.0 }
+    }
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self { enabled: true, ceiling_db: -1.0, release_ms: 80.0 }
+    }
+}
+
+impl EffectsConfig {
+    /// Checks the settings are in range, naming the first one that isn't.
+    pub fn validate(&self) -> Result<(), String> {
+        let unit = |name: &str, value: f32| {
+            if (0.0..=1.0).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("{} must be between 0 and 1 (got {})", name, value))
+            }
+        };
+        let positive = |name: &str, value: f32| {
+            if value > 0.0 && value.is_finite() {
+                Ok(())
+            } else {
+                Err(format!("{} must be positive (got {})", name, value))
+            }
+        };
+        if self.delay.beats.is_nan() || self.delay.beats <= 0.0 {
+            return Err(format!("delay.beats must be positive (got {})", self.delay.beats));
+        }
+        if !(0.0..1.0).contains(&self.delay.feedback) {
+            return Err(format!("delay.feedback must be at least 0 and below 1 (got {})", self.delay.feedback));
+        }
+        unit("delay.mix", self.delay.mix)?;
+        unit("reverb.room_size", self.reverb.room_size)?;
+        unit("reverb.damping", self.reverb.damping)?;
+        unit("reverb.mix", self.reverb.mix)?;
+        if self.compressor.ratio.is_nan() || self.compressor.ratio < 1.0 {
+            return Err(format!("compressor.ratio must be at least 1 (got {})", self.compressor.ratio));
+        }
+        if !self.compressor.threshold_db.is_finite() {
+            return Err("compressor.threshold_db must be finite".to_string());
+        }
+        positive("compressor.attack_ms", self.compressor.attack_ms)?;
+        positive("compressor.release_ms", self.compressor.release_ms)?;
+        if self.limiter.ceiling_db.is_nan() || self.limiter.ceiling_db > 0.0 {
+            return Err(format!("limiter.ceiling_db must not be above 0 (got {})", self.limiter.ceiling_db));
+        }
+        positive("limiter.release_ms", self.limiter.release_ms)
+    }
+}
+
+/// The effects chain, fed a block at a time.
+pub struct MasterBus {
+    sample_rate: u32,
+    tempo: TempoMap,
+    /// Samples processed so far, to find the tempo.
+    position: u64,
+    delay: Option<(Delay, DelayConfig)>,
+    reverb: Option<(Reverb, f32)>,
+    compressor: Option<Compressor>,
+    limiter: Option<Limiter>,
+    /// Output samples still to drop, to make up for the limiter's lookahead.
+    latency: usize,
+}
+
+impl MasterBus {
+    pub fn new(config: &EffectsConfig, tempo: &TempoMap, sample_rate: u32) -> Self {
+        let rate = sample_rate as f32;
+        let delay = config.delay.enabled.then(|| {
+            let longest = config.delay.beats * 60.0 / tempo.slowest_bpm();
+            (Delay::new((longest * f64::from(sample_rate)).ceil() as usize + 1), config.delay.clone())
+        });
+        let reverb = config.reverb.enabled.then(|| (Reverb::new(&config.reverb, sample_rate), config.reverb.mix));
+        let compressor = config.compressor.enabled.then(|| Compressor::new(&config.compressor, rate));
+        let limiter = config.limiter.enabled.then(|| Limiter::new(&config.limiter, rate));
+        let latency = limiter.as_ref().map_or(0, Limiter::latency);
+        Self { sample_rate, tempo: tempo.clone(), position: 0, delay, reverb, compressor, limiter, latency }
+    }
+
+    /// Mixes a block of the kick and the music, the same length, into
+    /// `out`. The mix lines up with the input once [`MasterBus::finish`]
+    /// has flushed it.
+    pub fn process(&mut self, kick: &[f32], music: &[f32], out: &mut Vec<f32>) {
+        debug_assert_eq!(kick.len(), music.len());
+        let rate = f64::from(self.sample_rate);
+        for (&kick, &music) in kick.iter().zip(music) {
+            let mut music = music;
+            if let Some((delay, config)) = &mut self.delay {
+                // Retimed every sample, so the echoes follow tempo changes
+                // within a block too
+                let bpm = self.tempo.bpm_at(self.position as f64 / rate);
+                let samples = ((config.beats * 60.0 / bpm * rate).round() as usize).clamp(1, delay.capacity());
+                music += delay.process(music, samples, config.feedback) * config.mix;
+            }
+            if let Some((reverb, mix)) = &mut self.reverb {
+                music += reverb.process(music) * *mix;
+            }
+            if let Some(compressor) = &mut self.compressor {
+                music *= compressor.gain(kick);
+            }
+            self.push(kick + music, out);
+            self.position += 1;
+        }
+    }
+
+    /// Lets the limiter's lookahead out, ending the mix.
+    pub fn finish(mut self, out: &mut Vec<f32>) {
+        for _ in 0..self.limiter.as_ref().map_or(0, Limiter::latency) {
+            self.push(0.0, out);
+        }
+    }
+
+    fn push(&mut self, sample: f32, out: &mut Vec<f32>) {
+        let sample = match &mut self.limiter {
+            Some(limiter) => limiter.process(sample),
+            None => sample,
+        };
+        if self.latency > 0 {
+            self.latency -= 1;
+        } else {
+            out.push(sample);
+        }
+    }
+}
+
+/// A feedback delay line, its echoes darkening as they repeat.
+struct Delay {
+    buffer: Vec<f32>,
+    pos: usize,
+    /// Low-pass state in the feedback path.
+    tone: f32,
+}
+
+impl Delay {
+    fn new(capacity: usize) -> Self {
+        Self { buffer: vec![0.0; capacity], pos: 0, tone: 0.0 }
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The echoes of `x`, `samples` late.
+    fn process(&mut self, x: f32, samples: usize, feedback: f32) -> f32 {
+        let len = self.buffer.len();
+        let echo = self.buffer[(self.pos + len - samples) % len];
+        self.tone += 0.3 * (echo - self.tone);
+        self.buffer[self.pos] = x + self.tone * feedback;
+        self.pos = (self.pos + 1) % len;
+        echo
+    }
+}
+
+/// Freeverb: parallel comb filters with damped feedback, then allpasses to
+/// diffuse them.
+struct Reverb {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+/// Delays of the comb and allpass filters at 44.1 kHz.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+
+/// Level into the combs, which sum to a lot more than their input.
+const REVERB_INPUT: f32 = 0.015;
+
+impl Reverb {
+    fn new(config: &ReverbConfig, sample_rate: u32) -> Self {
+        let scale = |samples: usize| (samples * sample_rate as usize / 44100).max(1);
+        let feedback = 0.7 + 0.28 * config.room_size;
+        let damping = 0.4 * config.damping;
+        Self {
+            combs: COMB_TUNING.iter().map(|&n| Comb::new(scale(n), feedback, damping)).collect(),
+            allpasses: ALLPASS_TUNING.iter().map(|&n| Allpass::new(scale(n))).collect(),
+        }
+    }
+
+    /// The reverberation of `x`, without `x` itself.
+    fn process(&mut self, x: f32) -> f32 {
+        let input = x * REVERB_INPUT;
+        let wet = self.combs.iter_mut().map(|comb| comb.process(input)).sum();
+        self.allpasses.iter_mut().fold(wet, |y, allpass| allpass.process(y))
+    }
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    pos: usize,
+    feedback: f32,
+    damping: f32,
+    store: f32,
+}
+
+impl Comb {
+    fn new(len: usize, feedback: f32, damping: f32) -> Self {
+        Self { buffer: vec![0.0; len], pos: 0, feedback, damping, store: 0.0 }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let out = self.buffer[self.pos];
+        self.store = out * (1.0 - self.damping) + self.store * self.damping;
+        self.buffer[self.pos] = x + self.store * self.feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        out
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len], pos: 0 }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        self.buffer[self.pos] = x + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+/// A feed-forward compressor whose level comes from a sidechain.
+struct Compressor {
+    threshold_db: f32,
+    slope: f32,
+    attack: f32,
+    release: f32,
+    /// Smoothed sidechain level.
+    envelope: f32,
+}
+
+impl Compressor {
+    fn new(config: &CompressorConfig, sample_rate: f32) -> Self {
+        Self {
+            threshold_db: config.threshold_db,
+            slope: 1.0 - 1.0 / config.ratio,
+            attack: smoothing(config.attack_ms, sample_rate),
+            release: smoothing(config.release_ms, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    /// Gain for the signal when the sidechain is at `key`.
+    fn gain(&mut self, key: f32) -> f32 {
+        let level = key.abs();
+        let coeff = if level > self.envelope { self.attack } else { self.release };
+        self.envelope = level + coeff * (self.envelope - level);
+        let over = to_db(self.envelope) - self.threshold_db;
+        if over > 0.0 {
+            from_db(-over * self.slope)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Taps per phase of the true-peak interpolator.
+const TRUE_PEAK_TAPS: usize = 16;
+/// Phases of the true-peak interpolator. This is 8x oversampling, twice ITU-R
+/// BS.1770's, which can miss peaks near the top of the band by a few tenths
+/// of a dB.
+const TRUE_PEAK_PHASES: usize = 8;
+/// Aimed under the ceiling, for the peaks between the interpolated phases
+/// and gain changes within the interpolator's window.
+const TRUE_PEAK_MARGIN_DB: f32 = 0.1;
+
+/// A lookahead limiter that holds the true peak of its output under the
+/// ceiling.
+///
+/// Each sample's peak is estimated by interpolating it with a windowed sinc
+/// at eight times the sample rate. The gain needed to bring the peak under
+/// the ceiling is held for the length of the lookahead plus the
+/// interpolator's window and averaged over the lookahead, so the gain has
+/// fully ramped down over every sample the peak was interpolated from by the
+/// time they come out of the delay line.
+struct Limiter {
+    ceiling: f32,
+    release: f32,
+    /// Samples of lookahead.
+    lookahead: usize,
+    /// Interpolation filter, by phase.
+    phases: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES],
+    /// The latest input, for the interpolator and the delay line.
+    history: VecDeque<f32>,
+    /// Gains needed over the lookahead, as `(index, gain)`, rising.
+    minimum: VecDeque<(u64, f32)>,
+    index: u64,
+    /// Released gain.
+    envelope: f32,
+    /// The last `lookahead` envelopes and their sum, for the average.
+    recent: VecDeque<f32>,
+    sum: f64,
+}
+
+impl Limiter {
+    fn new(config: &LimiterConfig, sample_rate: f32) -> Self {
+        let lookahead = ((sample_rate * 0.0015).round() as usize).max(1);
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES];
+        for (p, taps) in phases.iter_mut().enumerate() {
+            // Interpolates at p / PHASES past the middle of the window
+            let center = (TRUE_PEAK_TAPS / 2 - 1) as f32 + p as f32 / TRUE_PEAK_PHASES as f32;
+            for (i, tap) in taps.iter_mut().enumerate() {
+                let x = i as f32 - center;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let window = 0.5 + 0.5 * (PI * x / (TRUE_PEAK_TAPS / 2) as f32).cos();
+                *tap = sinc * window;
+            }
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+        }
+        Self {
+            ceiling: from_db(config.ceiling_db - TRUE_PEAK_MARGIN_DB),
+            release: smoothing(config.release_ms, sample_rate),
+            lookahead,
+            phases,
+            history: VecDeque::from(vec![0.0; TRUE_PEAK_TAPS + lookahead]),
+            minimum: VecDeque::new(),
+            index: 0,
+            envelope: 1.0,
+            recent: VecDeque::from(vec![1.0; lookahead]),
+            sum: lookahead as f64,
+        }
+    }
+
+    /// How many samples late the output is.
+    fn latency(&self) -> usize {
+        TRUE_PEAK_TAPS + self.lookahead - 1
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        self.history.pop_front();
+        self.history.push_back(x);
+
+        // The true peak around the middle of the interpolator's window
+        let window = self.history.range(self.history.len() - TRUE_PEAK_TAPS..);
+        let mut peak = 0.0f32;
+        for taps in &self.phases {
+            let y: f32 = window.clone().zip(taps).map(|(s, t)| s * t).sum();
+            peak = peak.max(y.abs());
+        }
+        let needed = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+
+        // The smallest gain needed over the lookahead and the interpolator's
+        // window, so every sample a peak is interpolated from gets the gain
+        while self.minimum.back().is_some_and(|&(_, g)| g >= needed) {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.index, needed));
+        while self.minimum.front().is_some_and(|&(i, _)| i + (self.lookahead + TRUE_PEAK_TAPS) as u64 <= self.index) {
+            self.minimum.pop_front();
+        }
+        self.index += 1;
+        let held = self.minimum.front().unwrap().1;
+
+        self.envelope = if held < self.envelope { held } else { held + self.release * (self.envelope - held) };
+        self.sum += f64::from(self.envelope) - f64::from(self.recent.pop_front().unwrap());
+        self.recent.push_back(self.envelope);
+        let gain = (self.sum / self.lookahead as f64) as f32;
+
+        // The middle of the window of the peaks whose gain is now fully
+        // ramped down
+        let delayed = self.history[0];
+        delayed * gain.min(1.0)
+    }
+}
+
+/// Per-sample coefficient of a one-pole smoother with a time constant of
+/// `ms`.
+fn smoothing(ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (ms * 0.001 * sample_rate)).exp()
+}
+
+fn to_db(level: f32) -> f32 {
+    20.0 * level.max(1e-9).log10()
+}
+
+fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    /// Peak of `samples` interpolated at 16 times the sample rate, with a
+    /// much longer filter than the limiter's.
+    fn true_peak(samples: &[f32]) -> f32 {
+        const HALF: isize = 32;
+        const PHASES: usize = 16;
+        let mut peak = 0.0f32;
+        for n in HALF..samples.len() as isize - HALF {
+            for p in 0..PHASES {
+                let t = n as f32 + p as f32 / PHASES as f32;
+                let y: f32 = (n - HALF + 1..=n + HALF)
+                    .map(|i| {
+                        let x = t - i as f32;
+                        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                        let window = 0.5 + 0.5 * (PI * x / HALF as f32).cos();
+                        samples[i as usize] * sinc * window
+                    })
+                    .sum();
+                peak = peak.max(y.abs());
+            }
+        }
+        peak
+    }
+
+    /// Every effect off, then `enable` turning some back on.
+    fn only(enable: impl FnOnce(&mut EffectsConfig)) -> EffectsConfig {
+        let mut config = EffectsConfig::default();
+        config.delay.enabled = false;
+        config.reverb.enabled = false;
+        config.compressor.enabled = false;
+        config.limiter.enabled = false;
+        enable(&mut config);
+        config
+    }
+
+    /// Runs `kick` and `music` through the bus in uneven blocks.
+    fn mix(config: &EffectsConfig, tempo: &TempoMap, sample_rate: u32, kick: &[f32], music: &[f32]) -> Vec<f32> {
+        let mut bus = MasterBus::new(config, tempo, sample_rate);
+        let mut out = Vec::new();
+        let mut start = 0;
+        for block in [1, 511, 4096].iter().cycle() {
+            if start == kick.len() {
+                break;
+            }
+            let end = (start + block).min(kick.len());
+            bus.process(&kick[start..end], &music[start..end], &mut out);
+            start = end;
+        }
+        bus.finish(&mut out);
+        assert_eq!(out.len(), kick.len());
+        out
+    }
+
+    fn impulses(len: usize, at: &[usize]) -> Vec<f32> {
+        let mut samples = vec![0.0; len];
+        at.iter().for_each(|&i| samples[i] = 1.0);
+        samples
+    }
+
+    #[test]
+    fn delay_echoes_an_impulse_one_beat_division_later() {
+        let config = only(|c| c.delay = DelayConfig { enabled: true, beats: 0.5, feedback: 0.0, mix: 0.4 });
+        // Half a beat at 120 BPM and 8 kHz
+        let tempo = TempoMap::new(120.0, &[], Default::default()).unwrap();
+        let out = mix(&config, &tempo, 8000, &[0.0; 6000], &impulses(6000, &[100]));
+        let echoes: Vec<(usize, f32)> = out.iter().copied().enumerate().filter(|&(_, s)| s != 0.0).collect();
+        assert_eq!(echoes, [(100, 1.0), (2100, 0.4)]);
+    }
+
+    #[test]
+    fn delay_follows_tempo_changes() {
+        let config = only(|c| c.delay = DelayConfig { enabled: true, beats: 0.5, feedback: 0.0, mix: 0.4 });
+        // 120 BPM for 4 beats (2 s), then 60 BPM: half a beat is 2000
+        // samples, then 4000
+        let tempo = TempoMap::new(120.0, &[(4.0, 60.0)], Default::default()).unwrap();
+        let out = mix(&config, &tempo, 8000, &[0.0; 30000], &impulses(30000, &[1000, 20000]));
+        let echoes: Vec<usize> = (0..out.len()).filter(|&i| out[i] != 0.0).collect();
+        assert_eq!(echoes, [1000, 3000, 20000, 24000]);
+    }
+
+    #[test]
+    fn reverb_tail_decays_and_stays_finite() {
+        let config = only(|c| c.reverb = ReverbConfig { enabled: true, room_size: 1.0, damping: 0.0, mix: 1.0 });
+        let tempo = TempoMap::new(120.0, &[], Default::default()).unwrap();
+        let len = 44100 * 8;
+        let out = mix(&config, &tempo, 44100, &vec![0.0; len], &impulses(len, &[0]));
+        assert!(out.iter().all(|s| s.is_finite()));
+
+        // Energy over successive half seconds, after the dry impulse
+        let energy: Vec<f32> = out[1..].chunks(22050).map(|c| c.iter().map(|s| s * s).sum()).collect();
+        assert!(energy[0] > 1e-3, "no tail: {:?}", energy);
+        for pair in energy.windows(2) {
+            assert!(pair[1] < pair[0], "the tail grew: {:?}", energy);
+        }
+        // At least 30 dB down by the end, even in the largest room
+        assert!(*energy.last().unwrap() < energy[0] * 1e-3, "{:?}", energy);
+    }
+
+    #[test]
+    fn compressor_ducks_under_the_kick_and_recovers() {
+        let config = only(|c| c.compressor.enabled = true);
+        let tempo = TempoMap::new(120.0, &[], Default::default()).unwrap();
+        let rate = 44100;
+        // A loud kick from 0.5 s to 0.75 s over steady music
+        let kick: Vec<f32> = (0..rate * 2)
+            .map(|i| if (22050..33075).contains(&i) { (TAU * 60.0 * i as f32 / 44100.0).sin() } else { 0.0 })
+            .collect();
+        let music = vec![0.1; kick.len()];
+        let out = mix(&config, &tempo, rate as u32, &kick, &music);
+        let gain = |secs: f32| {
+            let i = (secs * 44100.0) as usize;
+            (out[i] - kick[i]) / music[i]
+        };
+
+        assert!((gain(0.4) - 1.0).abs() < 1e-6);
+        // Down by more than 10 dB under the kick, after the attack
+        for secs in [0.52, 0.6, 0.7, 0.74] {
+            assert!(gain(secs) < from_db(-10.0), "gain {} at {} s", gain(secs), secs);
+        }
+        // Back up over the release, all the way within a second
+        assert!(gain(0.8) < gain(0.9) && gain(0.9) < gain(1.0));
+        assert!((gain(1.9) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn limiter_holds_the_true_peak_under_the_ceiling() {
+        let config = LimiterConfig { enabled: true, ceiling_db: -1.0, release_ms: 50.0 };
+        let mut limiter = Limiter::new(&config, SAMPLE_RATE);
+        let mut rng = StdRng::seed_from_u64(1);
+        let partials: Vec<(f32, f32)> =
+            (0..64).map(|_| (rng.gen_range(20.0..16000.0), rng.gen_range(0.0..TAU))).collect();
+        let dense = |t: f32| partials.iter().map(|&(f, phase)| (TAU * f * t / SAMPLE_RATE + phase).sin()).sum::<f32>();
+        // Loud bursts of a quarter-rate sine sampled halfway between its
+        // peaks, so its true peak is 3 dB over its samples, of dense partials
+        // and of a high sine, with a quiet sine in between
+        let input: Vec<f32> = (0..8000)
+            .map(|i| {
+                let t = i as f32;
+                match (i / 1000) % 4 {
+                    0 => 3.0 * (PI / 2.0 * t + PI / 4.0).sin(),
+                    1 => 0.2 * (TAU * 440.0 * t / SAMPLE_RATE).sin(),
+                    2 => 0.4 * dense(t),
+                    _ => 1.5 * (TAU * 9000.0 * t / SAMPLE_RATE).sin(),
+                }
+            })
+            .collect();
+        let output: Vec<f32> = input.iter().map(|&x| limiter.process(x)).collect();
+        let peak = to_db(true_peak(&output[limiter.latency()..]));
+        assert!(peak <= config.ceiling_db, "true peak of {} dB", peak);
+    }
+}
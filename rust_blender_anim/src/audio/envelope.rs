@@ -0,0 +1,85 @@
+//! ADSR envelopes.
+
+/// Attack, decay, sustain and release, with linear segments. Times are in
+/// seconds and the sustain is a level from 0 to 1.
+///
+/// The envelope is evaluated from the time since the note started and how
+/// long it's held, so notes can be rendered independently of each other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Adsr {
+    pub const fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self { attack, decay, sustain, release }
+    }
+
+    /// Level `t` seconds into a note held for `held` seconds.
+    pub fn level(&self, t: f32, held: f32) -> f32 {
+        if t < 0.0 {
+            return 0.0;
+        }
+        if t < held {
+            return self.held_level(t);
+        }
+        // Released from wherever the envelope had got to
+        let released = t - held;
+        if released >= self.release {
+            0.0
+        } else {
+            self.held_level(held) * (1.0 - released / self.release)
+        }
+    }
+
+    /// Seconds the note sounds for after it's released.
+    pub fn tail(&self) -> f32 {
+        self.release
+    }
+
+    fn held_level(&self, t: f32) -> f32 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADSR: Adsr = Adsr::new(0.1, 0.2, 0.5, 0.4);
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn held_notes_reach_the_sustain() {
+        assert_eq!(ADSR.level(-0.1, 1.0), 0.0);
+        assert!(close(ADSR.level(0.0, 1.0), 0.0));
+        assert!(close(ADSR.level(0.05, 1.0), 0.5));
+        assert!(close(ADSR.level(0.1, 1.0), 1.0));
+        assert!(close(ADSR.level(0.2, 1.0), 0.75));
+        assert!(close(ADSR.level(0.3, 1.0), 0.5));
+        assert!(close(ADSR.level(0.9, 1.0), 0.5));
+    }
+
+    #[test]
+    fn released_notes_fade_to_silence() {
+        assert!(close(ADSR.level(1.2, 1.0), 0.25));
+        assert!(close(ADSR.level(1.0 + ADSR.tail(), 1.0), 0.0));
+        assert_eq!(ADSR.level(5.0, 1.0), 0.0);
+        // Released during the attack, from the level it had got to
+        assert!(close(ADSR.level(0.05, 0.05), 0.5));
+        assert!(close(ADSR.level(0.25, 0.05), 0.25));
+        assert_eq!(ADSR.level(0.5, 0.05), 0.0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Radix-2 fast Fourier transform.
+
+use std::f32::consts::PI;
+
+/// Transforms the complex signal `re + i·im` in place. The length must be
+/// a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n, "FFT size must be a power of two");
+    if n < 2 {
+        return;
+    }
+
+    // Bit-reversed order, so the butterflies can work in place
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let twiddles: Vec<(f32, f32)> = (0..n / 2).map(|k| (-2.0 * PI * k as f32 / n as f32).sin_cos()).collect();
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        let step = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let (sin, cos) = twiddles[k * step];
+                let (a, b) = (start + k, start + k + half);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// A Hann window of `n` samples.
+pub fn hann(n: usize) -> Vec<f32> {
+    (0..n).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()).collect()
+}
+
+/// Magnitudes of the first `n / 2 + 1` bins of the real signal `samples`,
+/// multiplied by `window`.
+pub fn magnitudes(samples: &[f32], window: &[f32]) -> Vec<f32> {
+    let mut re: Vec<f32> = samples.iter().zip(window).map(|(s, w)| s * w).collect();
+    let mut im = vec![0.0; re.len()];
+    fft(&mut re, &mut im);
+    re.iter().zip(&im).take(re.len() / 2 + 1).map(|(r, i)| r.hypot(*i)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sine_peaks_in_its_bin() {
+        const N: usize = 1024;
+        // Exactly 37 cycles per window, and a quieter one off the bin grid
+        let samples: Vec<f32> = (0..N)
+            .map(|i| {
+                let t = i as f32 / N as f32;
+                (2.0 * PI * 37.0 * t).sin() + 0.2 * (2.0 * PI * 200.5 * t).sin()
+            })
+            .collect();
+        let bins = magnitudes(&samples, &vec![1.0; N]);
+        assert_eq!(bins.len(), N / 2 + 1);
+        let peak = (0..bins.len()).max_by(|&a, &b| bins[a].total_cmp(&bins[b])).unwrap();
+        assert_eq!(peak, 37);
+        // A full-scale sine puts half its energy in the positive bin
+        assert!((bins[37] - N as f32 / 2.0).abs() < 0.5, "{}", bins[37]);
+    }
+
+    #[test]
+    fn matches_the_direct_transform() {
+        let input: Vec<f32> = (0..16).map(|i| ((i * 7 + 3) % 11) as f32 - 5.0).collect();
+        let (mut re, mut im) = (input.clone(), vec![0.0; 16]);
+        fft(&mut re, &mut im);
+        for k in 0..16 {
+            let (mut dr, mut di) = (0.0, 0.0);
+            for (n, x) in input.iter().enumerate() {
+                let (sin, cos) = (-2.0 * PI * (k * n) as f32 / 16.0).sin_cos();
+                dr += x * cos;
+                di += x * sin;
+            }
+            assert!((re[k] - dr).abs() < 1e-3 && (im[k] - di).abs() < 1e-3, "bin {}", k);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Resonant filters.
+//!
+//! [`Biquad`] is the classic second-order filter with the coefficients of
+//! the RBJ Audio EQ Cookbook, for fixed settings. [`Svf`] is a trapezoidal
+//! state-variable filter (after Andrew Simper) that stays stable when its
+//! cutoff moves every sample, for envelope sweeps, and gives all three
+//! responses at once.
+
+use std::f32::consts::PI;
+
+/// Q of a filter with no resonance peak (Butterworth).
+pub const FLAT_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterKind {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    /// Transposed direct form II state.
+    s: [f32; 2],
+}
+
+impl Biquad {
+    pub fn new(kind: FilterKind, cutoff: f32, q: f32, sample_rate: u32) -> Self {
+        let mut filter = Self { b: [1.0, 0.0, 0.0], a: [0.0, 0.0], s: [0.0, 0.0] };
+        filter.set(kind, cutoff, q, sample_rate);
+        filter
+    }
+
+    /// Changes the settings, keeping the state.
+    pub fn set(&mut self, kind: FilterKind, cutoff: f32, q: f32, sample_rate: u32) {
+        let w0 = 2.0 * PI * clamp_cutoff(cutoff, sample_rate) / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q.max(1e-3));
+        let b = match kind {
+            FilterKind::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            FilterKind::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            // Constant 0 dB peak gain
+            FilterKind::BandPass => [alpha, 0.0, -alpha],
+        };
+        let a0 = 1.0 + alpha;
+        self.b = b.map(|b| b / a0);
+        self.a = [-2.0 * cos / a0, (1.0 - alpha) / a0];
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.s[0];
+        self.s[0] = self.b[1] * x - self.a[0] * y + self.s[1];
+        self.s[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The responses of an [`Svf`] to one sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SvfOutput {
+    pub low: f32,
+    pub band: f32,
+    pub high: f32,
+}
+
+impl SvfOutput {
+    pub fn get(&self, kind: FilterKind) -> f32 {
+        match kind {
+            FilterKind::LowPass => self.low,
+            FilterKind::HighPass => self.high,
+            FilterKind::BandPass => self.band,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Svf {
+    ic1: f32,
+    ic2: f32,
+}
+
+impl Svf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filters `x` with the given `cutoff` and `q`, which may change from
+    /// one sample to the next.
+    pub fn process(&mut self, x: f32, cutoff: f32, q: f32, sample_rate: u32) -> SvfOutput {
+        let g = (PI * clamp_cutoff(cutoff, sample_rate) / sample_rate as f32).tan();
+        let k = 1.0 / q.max(1e-3);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = x - self.ic2;
+        let v1 = a1 * self.ic1 + a2 * v3;
+        let v2 = self.ic2 + a2 * self.ic1 + a3 * v3;
+        self.ic1 = 2.0 * v1 - self.ic1;
+        self.ic2 = 2.0 * v2 - self.ic2;
+        SvfOutput { low: v2, band: v1, high: x - k * v1 - v2 }
+    }
+}
+
+/// Keeps the cutoff within the range the filters are stable in.
+fn clamp_cutoff(cutoff: f32, sample_rate: u32) -> f32 {
+    cutoff.clamp(10.0, sample_rate as f32 * 0.49)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// Peak level of a filtered sine at `frequency` once the filter settles.
+    fn gain(frequency: f32, mut filter: impl FnMut(f32) -> f32) -> f32 {
+        (0..8820)
+            .map(|i| filter((TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin()))
+            .skip(4410)
+            .fold(0.0, |peak, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn biquads_pass_and_stop_their_bands() {
+        let response = |kind, frequency| {
+            let mut filter = Biquad::new(kind, 1000.0, FLAT_Q, SAMPLE_RATE);
+            gain(frequency, |x| filter.process(x))
+        };
+        assert!(response(FilterKind::LowPass, 100.0) > 0.99);
+        assert!(response(FilterKind::LowPass, 10000.0) < 0.02);
+        assert!(response(FilterKind::HighPass, 100.0) < 0.02);
+        assert!(response(FilterKind::HighPass, 10000.0) > 0.99);
+        assert!((response(FilterKind::BandPass, 1000.0) - 1.0).abs() < 0.01);
+        // Butterworth is 3 dB down at the cutoff
+        assert!((response(FilterKind::LowPass, 1000.0) - FLAT_Q).abs() < 0.01);
+    }
+
+    #[test]
+    fn svf_matches_the_biquad_low_and_high_pass() {
+        for kind in [FilterKind::LowPass, FilterKind::HighPass] {
+            for frequency in [100.0, 1000.0, 10000.0] {
+                let mut biquad = Biquad::new(kind, 1000.0, FLAT_Q, SAMPLE_RATE);
+                let mut svf = Svf::new();
+                let expected = gain(frequency, |x| biquad.process(x));
+                let actual = gain(frequency, |x| svf.process(x, 1000.0, FLAT_Q, SAMPLE_RATE).get(kind));
+                assert!((actual - expected).abs() < 0.01, "{:?} at {} Hz: {} != {}", kind, frequency, actual, expected);
+            }
+        }
+    }
+}
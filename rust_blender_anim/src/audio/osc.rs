@@ -0,0 +1,125 @@
+//! Band-limited oscillators.
+//!
+//! The saw and square waves are corrected with PolyBLEP (polynomial
+//! band-limited steps): the jumps of the naive waveforms, which alias into
+//! audible tones below the fundamental, are smoothed over the sample on
+//! either side. The triangle integrates the band-limited square.
+
+use std::f32::consts::TAU;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    /// White noise, ignoring the frequency.
+    Noise,
+}
+
+#[derive(Clone, Debug)]
+pub struct Oscillator {
+    waveform: Waveform,
+    sample_rate: f32,
+    /// Position in the cycle, from 0 to 1.
+    phase: f32,
+    /// Phase advanced per sample.
+    increment: f32,
+    /// Integrated square, for the triangle.
+    triangle: f32,
+    rng: StdRng,
+}
+
+impl Oscillator {
+    /// An oscillator at `frequency` Hz, starting at the beginning of its
+    /// cycle. `seed` seeds the noise.
+    pub fn new(waveform: Waveform, frequency: f32, sample_rate: u32, seed: u64) -> Self {
+        let mut osc = Self {
+            waveform,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            increment: 0.0,
+            // The integrated square starts at the bottom of the triangle
+            triangle: -1.0,
+            rng: StdRng::seed_from_u64(seed),
+        };
+        osc.set_frequency(frequency);
+        osc
+    }
+
+    /// Changes the frequency without resetting the phase, for glides and
+    /// pitch envelopes.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        // Above Nyquist the corrections break down
+        self.increment = (frequency / self.sample_rate).clamp(0.0, 0.5);
+    }
+
+    /// The next sample, from -1 to 1.
+    pub fn next_sample(&mut self) -> f32 {
+        let (t, dt) = (self.phase, self.increment);
+        let square = || {
+            let naive = if t < 0.5 { 1.0 } else { -1.0 };
+            naive + poly_blep(t, dt) - poly_blep((t + 0.5).fract(), dt)
+        };
+        let value = match self.waveform {
+            Waveform::Sine => (t * TAU).sin(),
+            Waveform::Saw => 2.0 * t - 1.0 - poly_blep(t, dt),
+            Waveform::Square => square(),
+            Waveform::Triangle => {
+                // Leaks slightly, so rounding errors don't build up
+                self.triangle = 4.0 * dt * square() + (1.0 - 0.05 * dt) * self.triangle;
+                self.triangle
+            }
+            Waveform::Noise => self.rng.gen_range(-1.0..1.0),
+        };
+        self.phase = (self.phase + dt).fract();
+        value
+    }
+}
+
+/// Correction for a unit step at phase 0, `dt` being the phase increment.
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if dt <= 0.0 {
+        0.0
+    } else if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(waveform: Waveform, frequency: f32, seed: u64) -> Vec<f32> {
+        let mut osc = Oscillator::new(waveform, frequency, 44100, seed);
+        (0..44100).map(|_| osc.next_sample()).collect()
+    }
+
+    #[test]
+    fn waveforms_stay_in_range_without_offset() {
+        for waveform in [Waveform::Sine, Waveform::Saw, Waveform::Square, Waveform::Triangle, Waveform::Noise] {
+            let samples = render(waveform, 441.0, 1);
+            let peak = samples.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+            let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+            assert!(peak <= 1.1, "{:?} peaks at {}", waveform, peak);
+            assert!(peak >= 0.9, "{:?} only reaches {}", waveform, peak);
+            assert!(mean.abs() < 0.02, "{:?} is offset by {}", waveform, mean);
+        }
+    }
+
+    #[test]
+    fn noise_follows_the_seed() {
+        assert_eq!(render(Waveform::Noise, 0.0, 3), render(Waveform::Noise, 0.0, 3));
+        assert_ne!(render(Waveform::Noise, 0.0, 3), render(Waveform::Noise, 0.0, 4));
+    }
+}
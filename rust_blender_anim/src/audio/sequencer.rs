@@ -0,0 +1,473 @@
+//! A pattern sequencer for the synthesized soundtrack.
+//!
+//! A [`Song`] is a list of sections, each playing a named pattern some
+//! number of times. A pattern is a fixed number of steps with a track per
+//! instrument, written either as a grid (`"X...x..."`) or as a list of
+//! notes with their own pitch, velocity and length. Steps are counted in
+//! beats of the [`TempoMap`], so the song follows its tempo changes. Songs
+//! are loaded from JSON:
+//!
+//! ```json
+//! {
+//!   "steps_per_beat": 4,
+//!   "patterns": {
+//!     "beat": {
+//!       "steps": 16,
+//!       "tracks": [
+//!         { "instrument": "kick", "grid": "X...X...X...X..." },
+//!         { "instrument": "bass", "notes": [{ "step": 0, "note": 33, "length": 8 }] }
+//!       ]
+//!     }
+//!   },
+//!   "sections": [{ "pattern": "beat", "repeat": 4 }]
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use super::voice::{voice_seed, Instrument, NoteEvent, Voice};
+use super::BLOCK;
+use crate::tempo::TempoMap;
+
+/// The song played when no pattern file is configured.
+const DEFAULT_SONG: &str = include_str!("default_song.json");
+
+/// Velocity of a lowercase `x` in a grid; `X` is an accent at full velocity.
+const GRID_VELOCITY: f32 = 0.6;
+
+/// Blocks a [`SongStream`] renders at a time, about 3 s at 44.1 kHz: enough
+/// notes and blocks to keep the threads busy.
+const WINDOW_BLOCKS: usize = 32;
+
+#[derive(Debug)]
+pub enum SongError {
+    Parse(serde_json::Error),
+    /// A section plays a pattern that isn't defined.
+    UnknownPattern(String),
+    /// A track of a pattern has a step it doesn't know, or one outside the
+    /// pattern.
+    InvalidStep {
+        pattern: String,
+        message: String,
+    },
+    /// The song has no steps to play.
+    Empty,
+}
+
+impl fmt::Display for SongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SongError::Parse(e) => write!(f, "failed to parse song: {}", e),
+            SongError::UnknownPattern(name) => write!(f, "unknown pattern '{}'", name),
+            SongError::InvalidStep { pattern, message } => write!(f, "pattern '{}': {}", pattern, message),
+            SongError::Empty => write!(f, "the song is empty"),
+        }
+    }
+}
+
+impl std::error::Error for SongError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SongError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SongError> for std::io::Error {
+    fn from(err: SongError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Song {
+    #[serde(default = "default_steps_per_beat")]
+    pub steps_per_beat: u32,
+    pub patterns: HashMap<String, Pattern>,
+    /// Played in order, then from the start again.
+    pub sections: Vec<Section>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pattern {
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub pattern: String,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+}
+
+/// One instrument's part in a pattern.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Track {
+    pub instrument: Instrument,
+    /// A character per step: `X` accent, `x` hit, `.` or `-` rest. Spaces
+    /// and `|` are ignored, to group the steps.
+    #[serde(default)]
+    pub grid: String,
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    /// Scales the velocity of every note.
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Note {
+    pub step: u32,
+    /// MIDI note number, up to 127; the instrument's default when unset.
+    pub note: Option<u8>,
+    #[serde(default = "default_volume")]
+    pub velocity: f32,
+    /// In steps.
+    #[serde(default = "default_length")]
+    pub length: f32,
+}
+
+fn default_steps_per_beat() -> u32 {
+    4
+}
+
+fn default_steps() -> u32 {
+    16
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
+
+fn default_length() -> f32 {
+    1.0
+}
+
+impl Default for Song {
+    /// A four-on-the-floor groove with hi-hats, a bass line and pad chords.
+    fn default() -> Self {
+        Self::parse(DEFAULT_SONG).expect("the default song is valid")
+    }
+}
+
+impl Song {
+    /// Reads the song at `path`, see [`Song::parse`].
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Parses and validates a JSON song.
+    pub fn parse(text: &str) -> Result<Self, SongError> {
+        let song: Song = serde_json::from_str(text).map_err(SongError::Parse)?;
+        for (name, pattern) in &song.patterns {
+            let invalid = |message: String| Err(SongError::InvalidStep { pattern: name.clone(), message });
+            for track in &pattern.tracks {
+                let steps = grid_steps(&track.grid);
+                if let Some(c) = steps.iter().find(|c| !matches!(c, 'X' | 'x' | '.' | '-')) {
+                    return invalid(format!("unknown grid step '{}'", c));
+                }
+                if steps.len() > pattern.steps as usize {
+                    return invalid(format!("the grid has {} steps out of {}", steps.len(), pattern.steps));
+                }
+                if let Some(note) = track.notes.iter().find(|n| n.step >= pattern.steps) {
+                    return invalid(format!("note at step {} out of {}", note.step, pattern.steps));
+                }
+                if let Some(note) = track.notes.iter().filter_map(|n| n.note).find(|&note| note > 127) {
+                    return invalid(format!("MIDI note {} above 127", note));
+                }
+                if track.notes.iter().any(|n| n.length.is_nan() || n.length <= 0.0) {
+                    return invalid("note lengths must be positive".to_string());
+                }
+            }
+        }
+        if let Some(section) = song.sections.iter().find(|s| !song.patterns.contains_key(&s.pattern)) {
+            return Err(SongError::UnknownPattern(section.pattern.clone()));
+        }
+        if song.steps_per_beat == 0 || song.length_in_steps() == 0 {
+            return Err(SongError::Empty);
+        }
+        Ok(song)
+    }
+
+    /// Steps in one pass through the sections.
+    fn length_in_steps(&self) -> u64 {
+        self.sections.iter().map(|s| u64::from(s.repeat) * u64::from(self.patterns[&s.pattern].steps)).sum()
+    }
+
+    /// Every note starting within the first `duration_secs` of the song,
+    /// looping it as needed, timed by `tempo`, with voices seeded from `seed`.
+    pub fn events(&self, tempo: &TempoMap, duration_secs: f64, seed: u64) -> Vec<NoteEvent> {
+        let steps_per_beat = f64::from(self.steps_per_beat);
+        let secs = |step: f64| tempo.secs_at(step / steps_per_beat);
+        let mut events = Vec::new();
+        let mut offset = 0u64;
+        'song: loop {
+            for section in &self.sections {
+                let pattern = &self.patterns[&section.pattern];
+                for _ in 0..section.repeat {
+                    if secs(offset as f64) >= duration_secs {
+                        break 'song;
+                    }
+                    for track in &pattern.tracks {
+                        for note in track.hits() {
+                            let step = offset as f64 + f64::from(note.step);
+                            let start = secs(step);
+                            if start < duration_secs {
+                                events.push(NoteEvent {
+                                    instrument: track.instrument,
+                                    start,
+                                    length: secs(step + f64::from(note.length)) - start,
+                                    note: note.note.unwrap_or(track.instrument.default_note()),
+                                    velocity: (note.velocity * track.volume).clamp(0.0, 1.0),
+                                    seed: voice_seed(seed, events.len() as u64),
+                                });
+                            }
+                        }
+                    }
+                    offset += u64::from(pattern.steps);
+                }
+            }
+        }
+        events
+    }
+
+    /// Streams `duration_secs` of the song at `sample_rate`, timed by
+    /// `tempo`, as mono buses a window at a time. The same `seed` renders
+    /// the same samples.
+    pub fn stream(&self, tempo: &TempoMap, sample_rate: u32, duration_secs: u32, seed: u64) -> SongStream {
+        let mut events = self.events(tempo, f64::from(duration_secs), seed);
+        // Stable, so the notes are always mixed in the same order
+        events.sort_by_key(|event| event.first_sample(sample_rate));
+        SongStream {
+            events,
+            next_event: 0,
+            voices: Vec::new(),
+            sample_rate,
+            offset: 0,
+            len: sample_rate as usize * duration_secs as usize,
+        }
+    }
+}
+
+/// A song rendered a window of [`WINDOW_BLOCKS`] blocks at a time, so the
+/// memory it takes doesn't grow with the length of the track. Made by
+/// [`Song::stream`].
+///
+/// The notes starting in a window are rendered in parallel, each on its own.
+/// Then each block of the window is mixed in parallel from the notes sounding
+/// in it, always in the same order, so the sum doesn't depend on the threads.
+#[derive(Clone, Debug)]
+pub struct SongStream {
+    /// Every note of the song, by start.
+    events: Vec<NoteEvent>,
+    /// The first note not rendered yet.
+    next_event: usize,
+    /// Rendered notes still sounding, by start.
+    voices: Vec<(Instrument, Voice)>,
+    sample_rate: u32,
+    /// Sample of the track the next window starts at.
+    offset: usize,
+    len: usize,
+}
+
+impl Iterator for SongStream {
+    type Item = Buses;
+
+    fn next(&mut self) -> Option<Buses> {
+        if self.offset >= self.len {
+            return None;
+        }
+        let (start, end) = (self.offset, (self.offset + WINDOW_BLOCKS * BLOCK).min(self.len));
+        let sample_rate = self.sample_rate;
+        let starting = self.events[self.next_event..].partition_point(|event| event.first_sample(sample_rate) < end);
+        let events = &self.events[self.next_event..self.next_event + starting];
+        self.voices.par_extend(events.par_iter().map(|event| (event.instrument, event.render(sample_rate))));
+        self.next_event += starting;
+
+        let mut window = Buses { kick: vec![0.0; end - start], music: vec![0.0; end - start] };
+        let voices = &self.voices;
+        window.kick.par_chunks_mut(BLOCK).zip(window.music.par_chunks_mut(BLOCK)).enumerate().for_each(
+            |(i, (kick, music))| {
+                let offset = start + i * BLOCK;
+                for (instrument, voice) in voices {
+                    let bus = match instrument {
+                        Instrument::Kick => &mut *kick,
+                        _ => &mut *music,
+                    };
+                    voice.mix_into(bus, offset);
+                }
+            },
+        );
+        self.voices.retain(|(_, voice)| voice.end() > end);
+        self.offset = end;
+        Some(window)
+    }
+}
+
+/// A window of a rendered song, split for the master bus.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Buses {
+    /// The kick drum, which the rest is ducked under.
+    pub kick: Vec<f32>,
+    /// Every other instrument.
+    pub music: Vec<f32>,
+}
+
+impl Track {
+    /// The grid's hits followed by the notes.
+    fn hits(&self) -> impl Iterator<Item = Note> + '_ {
+        grid_steps(&self.grid)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(step, c)| {
+                let velocity = match c {
+                    'X' => 1.0,
+                    'x' => GRID_VELOCITY,
+                    _ => return None,
+                };
+                Some(Note { step: step as u32, note: None, velocity, length: 1.0 })
+            })
+            .chain(self.notes.iter().copied())
+    }
+}
+
+/// The steps of a grid, without the separators.
+fn grid_steps(grid: &str) -> Vec<char> {
+    grid.chars().filter(|c| !c.is_whitespace() && *c != '|').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempo::TimeSignature;
+
+    /// The example from the module docs, with `tracks` swapped in for the
+    /// `beat` pattern and `sections` for the arrangement.
+    fn song(tracks: &str, sections: &str) -> String {
+        format!(
+            r#"{{ "patterns": {{ "beat": {{ "steps": 16, "tracks": [{}] }} }}, "sections": [{}] }}"#,
+            tracks, sections
+        )
+    }
+
+    const TRACKS: &str = r#"
+        { "instrument": "kick", "grid": "X...x...X...x..." },
+        { "instrument": "bass", "notes": [{ "step": 0, "note": 45, "length": 8 }, { "step": 8, "velocity": 0.5 }] }
+    "#;
+
+    #[test]
+    fn a_valid_song_plays_on_the_tempo() {
+        let song = Song::parse(&song(TRACKS, r#"{ "pattern": "beat", "repeat": 2 }"#)).unwrap();
+        assert_eq!(song.steps_per_beat, 4);
+        // Two bars at 120 BPM take 4 s, then the song loops
+        let tempo = TempoMap::new(120.0, &[], TimeSignature::default()).unwrap();
+        let events = song.events(&tempo, 5.0, 0);
+
+        let kicks: Vec<(f64, f32)> =
+            events.iter().filter(|e| e.instrument == Instrument::Kick).map(|e| (e.start, e.velocity)).collect();
+        let beats: Vec<f64> = (0..10).map(|beat| beat as f64 * 0.5).collect();
+        assert_eq!(kicks.iter().map(|k| k.0).collect::<Vec<_>>(), beats);
+        assert_eq!(kicks[..2], [(0.0, 1.0), (0.5, GRID_VELOCITY)]);
+
+        let bass: Vec<&NoteEvent> = events.iter().filter(|e| e.instrument == Instrument::Bass).collect();
+        assert_eq!(bass.iter().map(|e| e.start).collect::<Vec<_>>(), [0.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!((bass[0].start, bass[0].length, bass[0].note), (0.0, 1.0, 45));
+        // The default note and length, at the note's velocity
+        assert_eq!((bass[1].start, bass[1].length, bass[1].note), (1.0, 0.125, Instrument::Bass.default_note()));
+        assert_eq!(bass[1].velocity, 0.5);
+    }
+
+    #[test]
+    fn the_default_song_is_valid() {
+        let song = Song::default();
+        assert!(song.length_in_steps() > 0);
+        let tempo = TempoMap::new(120.0, &[], TimeSignature::default()).unwrap();
+        let windows: Vec<Buses> = song.stream(&tempo, 8000, 2, 0).collect();
+        assert!(windows.iter().flat_map(|w| &w.kick).any(|s| s.abs() > 0.01));
+        assert!(windows.iter().flat_map(|w| &w.music).any(|s| s.abs() > 0.01));
+    }
+
+    #[test]
+    fn invalid_songs_are_rejected() {
+        let section = r#"{ "pattern": "beat" }"#;
+        assert!(matches!(Song::parse("{"), Err(SongError::Parse(_))));
+        assert!(matches!(
+            Song::parse(&song(TRACKS, r#"{ "pattern": "drop" }"#)),
+            Err(SongError::UnknownPattern(name)) if name == "drop"
+        ));
+
+        let invalid = |track: &str| match Song::parse(&song(track, section)) {
+            Err(SongError::InvalidStep { pattern, message }) => {
+                assert_eq!(pattern, "beat");
+                message
+            }
+            other => panic!("{}: {:?}", track, other),
+        };
+        assert_eq!(invalid(r#"{ "instrument": "kick", "grid": "X..o" }"#), "unknown grid step 'o'");
+        assert_eq!(
+            invalid(r#"{ "instrument": "kick", "grid": "X... X... X... X... X" }"#),
+            "the grid has 17 steps out of 16"
+        );
+        assert_eq!(invalid(r#"{ "instrument": "bass", "notes": [{ "step": 16 }] }"#), "note at step 16 out of 16");
+        assert_eq!(
+            invalid(r#"{ "instrument": "bass", "notes": [{ "step": 0, "note": 128 }] }"#),
+            "MIDI note 128 above 127"
+        );
+        assert_eq!(
+            invalid(r#"{ "instrument": "bass", "notes": [{ "step": 0, "length": 0 }] }"#),
+            "note lengths must be positive"
+        );
+
+        assert!(matches!(Song::parse(&song(TRACKS, "")), Err(SongError::Empty)));
+        assert!(matches!(Song::parse(&song(TRACKS, r#"{ "pattern": "beat", "repeat": 0 }"#)), Err(SongError::Empty)));
+        let no_steps = song(TRACKS, section).replacen("{", r#"{ "steps_per_beat": 0,"#, 1);
+        assert!(matches!(Song::parse(&no_steps), Err(SongError::Empty)));
+    }
+
+    #[test]
+    fn streamed_windows_match_mixing_the_whole_track() {
+        let (song, tempo) = (Song::default(), TempoMap::constant(128.0));
+        // Several windows at a low rate, with notes ringing across their edges
+        let (sample_rate, duration_secs) = (8000, 40);
+        let len = sample_rate as usize * duration_secs as usize;
+        let mut expected = Buses { kick: vec![0.0; len], music: vec![0.0; len] };
+        let mut events = song.events(&tempo, f64::from(duration_secs), 1);
+        events.sort_by_key(|event| event.first_sample(sample_rate));
+        for event in events {
+            let bus = match event.instrument {
+                Instrument::Kick => &mut expected.kick,
+                _ => &mut expected.music,
+            };
+            event.render(sample_rate).mix_into(bus, 0);
+        }
+
+        let windows: Vec<Buses> = song.stream(&tempo, sample_rate, duration_secs, 1).collect();
+        assert!(windows.len() > 2);
+        assert_eq!(windows.iter().map(|w| w.kick.len()).sum::<usize>(), len);
+        assert_eq!(windows.iter().flat_map(|w| w.kick.iter().copied()).collect::<Vec<_>>(), expected.kick);
+        assert_eq!(windows.iter().flat_map(|w| w.music.iter().copied()).collect::<Vec<_>>(), expected.music);
+    }
+
+    #[test]
+    fn long_tracks_dont_overflow() {
+        let stream = Song::default().stream(&TempoMap::constant(120.0), 192_000, 30_000, 0);
+        assert_eq!(stream.len, 5_760_000_000);
+    }
+}
@@ -0,0 +1,196 @@
+//! The synth's instruments.
+//!
+//! Each note is rendered on its own and mixed into the track, so voices
+//! don't need to know about each other or the tempo.
+
+use serde::Deserialize;
+
+use super::envelope::Adsr;
+use super::filter::{Biquad, FilterKind, Svf, FLAT_Q};
+use super::osc::{Oscillator, Waveform};
+
+/// Loudness of the bass over a note.
+const BASS_AMP: Adsr = Adsr::new(0.005, 0.1, 0.8, 0.02);
+/// How far the bass filter is open over a note.
+const BASS_FILTER: Adsr = Adsr::new(0.002, 0.2, 0.15, 0.02);
+/// Loudness of the pad over a note.
+const PAD_AMP: Adsr = Adsr::new(0.3, 0.4, 0.8, 0.6);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Instrument {
+    Kick,
+    Snare,
+    Hat,
+    Bass,
+    Pad,
+}
+
+/// A note to play, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteEvent {
+    pub instrument: Instrument,
+    pub start: f64,
+    /// How long the note is held. Drums ignore it and ring out.
+    pub length: f64,
+    /// MIDI note number. Drums ignore it.
+    pub note: u8,
+    /// From 0 to 1.
+    pub velocity: f32,
+    /// Seeds the voice's noise, see [`voice_seed`].
+    pub seed: u64,
+}
+
+/// A note rendered on its own, to be mixed into the track.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Voice {
+    /// Sample of the track the note starts at.
+    pub start: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Instrument {
+    /// The note played when a pattern doesn't give one: A1 for the bass
+    /// (55 Hz) and A3 for the pad.
+    pub fn default_note(self) -> u8 {
+        match self {
+            Instrument::Pad => 57,
+            _ => 33,
+        }
+    }
+
+    /// Level in the mix.
+    fn gain(self) -> f32 {
+        match self {
+            Instrument::Kick => 0.5,
+            Instrument::Snare => 0.35,
+            Instrument::Hat => 0.1,
+            Instrument::Bass => 0.2,
+            Instrument::Pad => 0.06,
+        }
+    }
+
+    /// How long the voice sounds after the note is released.
+    fn tail(self) -> f64 {
+        match self {
+            Instrument::Kick => 0.5,
+            Instrument::Snare => 0.3,
+            Instrument::Hat => 0.1,
+            Instrument::Bass => f64::from(BASS_AMP.tail()),
+            Instrument::Pad => f64::from(PAD_AMP.tail()),
+        }
+    }
+}
+
+/// The seed of the `index`th note of a song rendered with `seed`. Every
+/// note gets its own, so a note sounds the same however the song is split
+/// up for rendering.
+pub fn voice_seed(seed: u64, index: u64) -> u64 {
+    // SplitMix64, so neighboring notes get unrelated seeds
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Frequency of a MIDI note in Hz.
+pub fn note_frequency(note: u8) -> f32 {
+    440.0 * 2f32.powf((f32::from(note) - 69.0) / 12.0)
+}
+
+impl Voice {
+    /// Sample of the track after the note's last.
+    pub fn end(&self) -> usize {
+        self.start + self.samples.len()
+    }
+
+    /// Adds the part of the note that falls in `out`, a block of the track
+    /// starting at sample `offset`.
+    pub fn mix_into(&self, out: &mut [f32], offset: usize) {
+        let from = self.start.max(offset);
+        let to = self.end().min(offset + out.len());
+        if from >= to {
+            return;
+        }
+        let samples = &self.samples[from - self.start..to - self.start];
+        for (out, sample) in out[from - offset..to - offset].iter_mut().zip(samples) {
+            *out += sample;
+        }
+    }
+}
+
+impl NoteEvent {
+    /// Sample of the track the note starts at, at `sample_rate`.
+    pub fn first_sample(&self, sample_rate: u32) -> usize {
+        (self.start * f64::from(sample_rate)).round().max(0.0) as usize
+    }
+
+    /// Renders the note at `sample_rate`, tail and all.
+    pub fn render(&self, sample_rate: u32) -> Voice {
+        let rate = f64::from(sample_rate);
+        let first = self.first_sample(sample_rate);
+        let drum = matches!(self.instrument, Instrument::Kick | Instrument::Snare | Instrument::Hat);
+        let held = if drum { 0.0 } else { self.length };
+        let last = ((self.start + held + self.instrument.tail()) * rate).round() as usize;
+        let mut samples = vec![0.0; last.saturating_sub(first)];
+
+        let out = &mut samples[..];
+        let gain = self.instrument.gain() * self.velocity;
+        let held = held as f32;
+        let frequency = note_frequency(self.note);
+        let dt = 1.0 / sample_rate as f32;
+        let time = |i: usize| i as f32 * dt;
+        match self.instrument {
+            Instrument::Kick => {
+                // Sine with a falling pitch
+                let mut osc = Oscillator::new(Waveform::Sine, 150.0, sample_rate, self.seed);
+                for (i, sample) in out.iter_mut().enumerate() {
+                    let env = (-time(i) * 20.0).exp();
+                    osc.set_frequency(50.0 + 100.0 * env);
+                    *sample += osc.next_sample() * env * gain;
+                }
+            }
+            Instrument::Snare => {
+                let mut tone = Oscillator::new(Waveform::Sine, 185.0, sample_rate, self.seed);
+                let mut noise = Oscillator::new(Waveform::Noise, 0.0, sample_rate, self.seed);
+                let mut filter = Biquad::new(FilterKind::HighPass, 1000.0, FLAT_Q, sample_rate);
+                for (i, sample) in out.iter_mut().enumerate() {
+                    let t = time(i);
+                    let tone = tone.next_sample() * (-t * 30.0).exp();
+                    let noise = filter.process(noise.next_sample()) * (-t * 20.0).exp();
+                    *sample += (0.4 * tone + 0.6 * noise) * gain;
+                }
+            }
+            Instrument::Hat => {
+                let mut noise = Oscillator::new(Waveform::Noise, 0.0, sample_rate, self.seed);
+                let mut filter = Biquad::new(FilterKind::HighPass, 7000.0, FLAT_Q, sample_rate);
+                for (i, sample) in out.iter_mut().enumerate() {
+                    *sample += filter.process(noise.next_sample()) * (-time(i) * 60.0).exp() * gain;
+                }
+            }
+            Instrument::Bass => {
+                // A saw through a resonant low-pass that opens on each note,
+                // more so the harder it's played
+                let mut osc = Oscillator::new(Waveform::Saw, frequency, sample_rate, self.seed);
+                let mut filter = Svf::new();
+                for (i, sample) in out.iter_mut().enumerate() {
+                    let t = time(i);
+                    let cutoff = 2.0 * frequency + 2000.0 * self.velocity * BASS_FILTER.level(t, held);
+                    let value = filter.process(osc.next_sample(), cutoff, 2.0, sample_rate).low;
+                    *sample += value * BASS_AMP.level(t, held) * gain;
+                }
+            }
+            Instrument::Pad => {
+                // Three detuned saws, swelling in, with the fizz taken off
+                let mut saws = [-0.004, 0.0, 0.004]
+                    .map(|detune| Oscillator::new(Waveform::Saw, frequency * (1.0 + detune), sample_rate, self.seed));
+                let mut filter = Biquad::new(FilterKind::LowPass, 2500.0, FLAT_Q, sample_rate);
+                for (i, sample) in out.iter_mut().enumerate() {
+                    let value = saws.iter_mut().map(Oscillator::next_sample).sum::<f32>() / 3.0;
+                    *sample += filter.process(value) * PAD_AMP.level(time(i), held) * gain;
+                }
+            }
+        }
+        Voice { start: first, samples }
+    }
+}
@@ -0,0 +1,515 @@
+//! WAV decoding and encoding.
+//!
+//! [`Wav`] reads integer PCM (8, 16, 24 or 32 bits) and IEEE float (32 or
+//! 64 bits) files, including `WAVE_FORMAT_EXTENSIBLE` and RF64 ones.
+//! Samples are converted to `f32` in `-1..1`; chunks other than `fmt `,
+//! `ds64` and `data` are skipped.
+//!
+//! [`WavWriter`] streams samples to a file in one of the [`SampleFormat`]s
+//! and fills in the chunk sizes when it's finalized. Files over 4 GB, whose
+//! sizes don't fit the 32-bit RIFF fields, are written as RF64 (EBU Tech
+//! 3306) instead: the writer reserves room for the `ds64` chunk up front as
+//! a `JUNK` chunk, and turns it into a `ds64` chunk holding the 64-bit
+//! sizes when needed.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Size of the `ds64` chunk body: RIFF size, data size, sample count and an
+/// empty table.
+const DS64_SIZE: u32 = 28;
+/// The tail of the `KSDATAFORMAT_SUBTYPE_*` GUIDs, after the format tag.
+const SUBTYPE_GUID: [u8; 14] = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+
+#[derive(Debug, PartialEq)]
+pub enum WavError {
+    /// Not a RIFF/WAVE file.
+    NotWave,
+    /// A required chunk is missing.
+    MissingChunk(&'static str),
+    /// The sample format isn't one of the supported ones.
+    Unsupported { format: u16, bits: u16 },
+    /// The file ended in the middle of a chunk.
+    Truncated,
+    /// A header field of a [`WavSpec`] overflows its width.
+    Overflow(&'static str),
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::NotWave => write!(f, "not a WAV file"),
+            WavError::MissingChunk(id) => write!(f, "WAV file has no '{}' chunk", id),
+            WavError::Unsupported { format, bits } => {
+                write!(f, "unsupported WAV sample format {} with {} bits", format, bits)
+            }
+            WavError::Truncated => write!(f, "truncated WAV file"),
+            WavError::Overflow(field) => write!(f, "the WAV {} is too large for its header field", field),
+        }
+    }
+}
+
+impl std::error::Error for WavError {}
+
+impl From<WavError> for std::io::Error {
+    fn from(err: WavError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Decoded audio.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved samples in `-1..1`.
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    /// Reads and decodes the WAV file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::decode(&std::fs::read(path)?)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, WavError> {
+        if bytes.len() < 12 || !matches!(&bytes[0..4], b"RIFF" | b"RF64") || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+
+        let mut format = None;
+        let mut data = None;
+        // The real data size of an RF64 file
+        let mut data_size = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let mut size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            if id == b"data" && size == u32::MAX as usize {
+                size = data_size.unwrap_or(size);
+            }
+            let body = bytes.get(pos + 8..pos.saturating_add(8 + size));
+            match id {
+                b"fmt " => format = Some(Format::parse(body.ok_or(WavError::Truncated)?)?),
+                b"ds64" => {
+                    let body = body.filter(|b| b.len() >= 16).ok_or(WavError::Truncated)?;
+                    data_size = Some(u64::from_le_bytes(body[8..16].try_into().unwrap()) as usize);
+                }
+                // Some writers leave the size of a streamed data chunk unset
+                b"data" => data = Some(body.unwrap_or(&bytes[pos + 8..])),
+                _ => {}
+            }
+            // Chunks are padded to an even size
+            pos = pos.saturating_add(8 + size + size % 2);
+        }
+
+        let format = format.ok_or(WavError::MissingChunk("fmt "))?;
+        let data = data.ok_or(WavError::MissingChunk("data"))?;
+        let width = usize::from(format.bits / 8);
+        let frame = width * usize::from(format.channels);
+        // Drop a trailing partial frame
+        let data = &data[..data.len() - data.len() % frame];
+        let samples = data.chunks_exact(width).map(|s| format.sample(s)).collect();
+        Ok(Self { sample_rate: format.sample_rate, channels: format.channels, samples })
+    }
+
+    /// The channels averaged into one.
+    pub fn mono(&self) -> Vec<f32> {
+        let channels = usize::from(self.channels);
+        self.samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect()
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.samples.len() as f64 / f64::from(self.channels) / f64::from(self.sample_rate)
+    }
+}
+
+/// The parts of the `fmt ` chunk needed to decode the samples.
+struct Format {
+    float: bool,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+impl Format {
+    fn parse(chunk: &[u8]) -> Result<Self, WavError> {
+        if chunk.len() < 16 {
+            return Err(WavError::Truncated);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([chunk[i], chunk[i + 1]]);
+        let mut tag = u16_at(0);
+        let channels = u16_at(2);
+        let sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+        let bits = u16_at(14);
+        if tag == FORMAT_EXTENSIBLE {
+            // The real format is the first two bytes of the sub-format GUID
+            if chunk.len() < 26 {
+                return Err(WavError::Truncated);
+            }
+            tag = u16_at(24);
+        }
+
+        let float = match (tag, bits) {
+            (FORMAT_PCM, 8 | 16 | 24 | 32) => false,
+            (FORMAT_FLOAT, 32 | 64) => true,
+            (format, bits) => return Err(WavError::Unsupported { format, bits }),
+        };
+        if channels == 0 || sample_rate == 0 {
+            return Err(WavError::Unsupported { format: tag, bits });
+        }
+        Ok(Self { float, channels, sample_rate, bits })
+    }
+
+    /// Decodes one little-endian sample.
+    fn sample(&self, bytes: &[u8]) -> f32 {
+        match (self.float, self.bits) {
+            (true, 32) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            (true, _) => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            // 8-bit samples are unsigned
+            (false, 8) => (f32::from(bytes[0]) - 128.0) / 128.0,
+            (false, 16) => f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0,
+            (false, 24) => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2_147_483_648.0,
+            (false, _) => i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2_147_483_648.0,
+        }
+    }
+}
+
+/// Sample encodings [`WavWriter`] can write.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    /// 16-bit integer PCM.
+    #[default]
+    I16,
+    /// 24-bit integer PCM.
+    I24,
+    /// 32-bit IEEE float.
+    F32,
+}
+
+impl SampleFormat {
+    pub fn bits(self) -> u16 {
+        match self {
+            SampleFormat::I16 => 16,
+            SampleFormat::I24 => 24,
+            SampleFormat::F32 => 32,
+        }
+    }
+}
+
+/// Layout of the audio written by [`WavWriter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavSpec {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub format: SampleFormat,
+}
+
+impl Default for WavSpec {
+    /// Mono 16-bit at 44.1 kHz.
+    fn default() -> Self {
+        Self { channels: 1, sample_rate: 44100, format: SampleFormat::I16 }
+    }
+}
+
+impl WavSpec {
+    /// Bytes per frame (one sample of every channel).
+    pub fn block_align(&self) -> Result<u16, WavError> {
+        let bytes = u32::from(self.channels) * u32::from(self.format.bits() / 8);
+        u16::try_from(bytes).map_err(|_| WavError::Overflow("block align"))
+    }
+
+    /// Bytes per second.
+    pub fn byte_rate(&self) -> Result<u32, WavError> {
+        self.sample_rate.checked_mul(u32::from(self.block_align()?)).ok_or(WavError::Overflow("byte rate"))
+    }
+
+    /// `WAVE_FORMAT_EXTENSIBLE` is required for more than two channels or
+    /// 16 bits; plain PCM is kept otherwise, for older readers.
+    fn extensible(&self) -> bool {
+        self.channels > 2 || self.format != SampleFormat::I16
+    }
+
+    /// The `fmt ` chunk body.
+    fn fmt_chunk(&self) -> Result<Vec<u8>, WavError> {
+        let tag = match self.format {
+            SampleFormat::F32 => FORMAT_FLOAT,
+            _ => FORMAT_PCM,
+        };
+        let bits = self.format.bits();
+        let mut chunk = Vec::with_capacity(40);
+        chunk.extend_from_slice(&(if self.extensible() { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+        chunk.extend_from_slice(&self.channels.to_le_bytes());
+        chunk.extend_from_slice(&self.sample_rate.to_le_bytes());
+        chunk.extend_from_slice(&self.byte_rate()?.to_le_bytes());
+        chunk.extend_from_slice(&self.block_align()?.to_le_bytes());
+        chunk.extend_from_slice(&bits.to_le_bytes());
+        if self.extensible() {
+            chunk.extend_from_slice(&22u16.to_le_bytes()); // Extension size
+            chunk.extend_from_slice(&bits.to_le_bytes()); // Valid bits per sample
+            let mask = if self.channels <= 18 { (1u32 << self.channels) - 1 } else { 0 };
+            chunk.extend_from_slice(&mask.to_le_bytes()); // Speakers, in the standard order
+            chunk.extend_from_slice(&tag.to_le_bytes());
+            chunk.extend_from_slice(&SUBTYPE_GUID);
+        }
+        Ok(chunk)
+    }
+}
+
+/// Streams interleaved samples to a WAV file.
+///
+/// The header is rewritten with the final sizes by [`WavWriter::finalize`],
+/// which must be called for the file to be valid.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    spec: WavSpec,
+    /// Bytes of sample data written so far.
+    data_size: u64,
+    always_rf64: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates the WAV file at `path`.
+    pub fn create(path: impl AsRef<Path>, spec: WavSpec) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), spec)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes a placeholder header to `writer`, which should be empty. Fails
+    /// if `spec` doesn't fit the header fields.
+    pub fn new(writer: W, spec: WavSpec) -> std::io::Result<Self> {
+        if spec.channels == 0 || spec.sample_rate == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "WAV needs channels and a sample rate"));
+        }
+        let mut writer = Self { writer, spec, data_size: 0, always_rf64: false };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /// Writes RF64 even when the file is small enough for plain RIFF.
+    pub fn always_rf64(mut self) -> Self {
+        self.always_rf64 = true;
+        self
+    }
+
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Appends interleaved `samples`, clamping them to `-1..1`.
+    pub fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        let width = usize::from(self.spec.format.bits() / 8);
+        let mut bytes = Vec::with_capacity(samples.len() * width);
+        for &sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self.spec.format {
+                SampleFormat::I16 => bytes.extend_from_slice(&((sample * 32767.0).round() as i16).to_le_bytes()),
+                SampleFormat::I24 => {
+                    bytes.extend_from_slice(&((sample * 8_388_607.0).round() as i32).to_le_bytes()[..3])
+                }
+                SampleFormat::F32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Pads the data chunk, fills in the header and returns the underlying
+    /// writer.
+    pub fn finalize(mut self) -> std::io::Result<W> {
+        if self.data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// The RIFF header, `JUNK`/`ds64`, `fmt ` and the `data` chunk header.
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let fmt = self.spec.fmt_chunk()?;
+        // Everything after the RIFF size field
+        let header = 4 + (8 + u64::from(DS64_SIZE)) + (8 + fmt.len() as u64) + 8;
+        let riff_size = header + self.data_size + self.data_size % 2;
+        let rf64 = self.always_rf64 || riff_size > u64::from(u32::MAX);
+
+        let mut bytes = Vec::with_capacity(header as usize + 8);
+        bytes.extend_from_slice(if rf64 { b"RF64" } else { b"RIFF" });
+        bytes.extend_from_slice(&(if rf64 { u32::MAX } else { riff_size as u32 }).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(if rf64 { b"ds64" } else { b"JUNK" });
+        bytes.extend_from_slice(&DS64_SIZE.to_le_bytes());
+        if rf64 {
+            let frames = self.data_size / u64::from(self.spec.block_align()?);
+            bytes.extend_from_slice(&riff_size.to_le_bytes());
+            bytes.extend_from_slice(&self.data_size.to_le_bytes());
+            bytes.extend_from_slice(&frames.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes()); // No table entries
+        } else {
+            bytes.extend_from_slice(&[0; DS64_SIZE as usize]);
+        }
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&fmt);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(if rf64 { u32::MAX } else { self.data_size as u32 }).to_le_bytes());
+        self.writer.write_all(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    /// A `fmt ` chunk body; extensible ones carry `tag` in the sub-format.
+    fn format(tag: u16, channels: u16, bits: u16, extensible: bool) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend((if extensible { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(RATE.to_le_bytes());
+        fmt.extend((RATE * u32::from(block_align)).to_le_bytes());
+        fmt.extend(block_align.to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        if extensible {
+            fmt.extend(22u16.to_le_bytes());
+            fmt.extend(bits.to_le_bytes());
+            fmt.extend(0u32.to_le_bytes());
+            fmt.extend(tag.to_le_bytes());
+            fmt.extend([0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        }
+        fmt
+    }
+
+    /// A RIFF file with `fmt`, an odd-sized chunk to skip and `data`.
+    fn riff(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        for (id, body) in [(b"fmt ", fmt), (b"LIST", &b"odd"[..]), (b"data", data)] {
+            chunks.extend(id);
+            chunks.extend((body.len() as u32).to_le_bytes());
+            chunks.extend(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((chunks.len() as u32 + 4).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(chunks);
+        bytes
+    }
+
+    fn assert_samples(wav: &Wav, expected: &[f32]) {
+        assert_eq!(wav.samples.len(), expected.len());
+        for (a, b) in wav.samples.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6, "{:?} != {:?}", wav.samples, expected);
+        }
+    }
+
+    #[test]
+    fn every_sample_format_decodes_to_the_same_values() {
+        let expected: [f32; 3] = [0.0, 0.5, -1.0];
+        let int = |bits: u16, values: [i32; 3]| -> Vec<u8> {
+            values.iter().flat_map(|v| v.to_le_bytes()[..usize::from(bits / 8)].to_vec()).collect()
+        };
+        let formats: [(u16, u16, Vec<u8>); 6] = [
+            (FORMAT_PCM, 8, vec![128, 192, 0]),
+            (FORMAT_PCM, 16, int(16, [0, 0x4000, -0x8000])),
+            (FORMAT_PCM, 24, int(24, [0, 0x40_0000, -0x80_0000])),
+            (FORMAT_PCM, 32, int(32, [0, 0x4000_0000, i32::MIN])),
+            (FORMAT_FLOAT, 32, expected.iter().flat_map(|v| v.to_le_bytes()).collect()),
+            (FORMAT_FLOAT, 64, expected.iter().flat_map(|&v| f64::from(v).to_le_bytes()).collect()),
+        ];
+        for (tag, bits, data) in formats {
+            for extensible in [false, true] {
+                let wav = Wav::decode(&riff(&format(tag, 1, bits, extensible), &data)).unwrap();
+                assert_eq!((wav.sample_rate, wav.channels), (RATE, 1));
+                assert_samples(&wav, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn channels_are_interleaved() {
+        let data: Vec<u8> = [0x4000i16, -0x4000, 0x2000, 0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let wav = Wav::decode(&riff(&format(FORMAT_PCM, 2, 16, true), &data)).unwrap();
+        assert_samples(&wav, &[0.5, -0.5, 0.25, 0.0]);
+        assert_eq!(wav.mono(), [0.0, 0.125]);
+        assert_eq!(wav.duration_secs(), 2.0 / f64::from(RATE));
+    }
+
+    #[test]
+    fn rf64_takes_the_data_size_from_ds64() {
+        let fmt = format(FORMAT_PCM, 1, 16, false);
+        let samples: Vec<u8> = [0x4000i16, -0x4000].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut bytes = b"RF64".to_vec();
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(b"ds64");
+        bytes.extend(DS64_SIZE.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes()); // RIFF size, unused by the decoder
+        bytes.extend((samples.len() as u64).to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(b"fmt ");
+        bytes.extend((fmt.len() as u32).to_le_bytes());
+        bytes.extend(&fmt);
+        bytes.extend(b"data");
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(&samples);
+        // A chunk after the data, which only the ds64 size tells apart
+        bytes.extend(b"LIST");
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend(b"INFO");
+
+        let wav = Wav::decode(&bytes).unwrap();
+        assert_samples(&wav, &[0.5, -0.5]);
+    }
+
+    #[test]
+    fn specs_that_overflow_the_header_are_rejected() {
+        let spec = WavSpec { channels: 6, sample_rate: 48000, format: SampleFormat::I24 };
+        assert_eq!((spec.block_align(), spec.byte_rate()), (Ok(18), Ok(864_000)));
+
+        let wide = WavSpec { channels: u16::MAX, format: SampleFormat::F32, ..spec };
+        assert_eq!(wide.block_align(), Err(WavError::Overflow("block align")));
+        assert_eq!(wide.byte_rate(), Err(WavError::Overflow("block align")));
+        let fast = WavSpec { sample_rate: u32::MAX, ..spec };
+        assert_eq!(fast.byte_rate(), Err(WavError::Overflow("byte rate")));
+        assert!(WavWriter::new(std::io::Cursor::new(Vec::new()), fast).is_err());
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let fmt = format(FORMAT_PCM, 1, 16, false);
+        assert_eq!(Wav::decode(b"RIFF\0\0\0\0AVI "), Err(WavError::NotWave));
+        assert_eq!(
+            Wav::decode(&riff(&format(FORMAT_PCM, 1, 12, false), &[])),
+            Err(WavError::Unsupported { format: 1, bits: 12 })
+        );
+        assert_eq!(
+            Wav::decode(&riff(&format(FORMAT_FLOAT, 1, 16, true), &[])),
+            Err(WavError::Unsupported { format: 3, bits: 16 })
+        );
+        assert_eq!(Wav::decode(&riff(&fmt[..12], &[])), Err(WavError::Truncated));
+
+        let mut no_data = riff(&fmt, &[]);
+        no_data.truncate(no_data.len() - 8);
+        assert_eq!(Wav::decode(&no_data), Err(WavError::MissingChunk("data")));
+    }
+}
@@ -0,0 +1,433 @@
+//! BVH (Biovision Hierarchy) motion capture export and parsing.
+//!
+//! Each object becomes a joint, nested like in Blender, with its frame 0
+//! location as the `OFFSET`. Rotations are written as `Zrotation Xrotation
+//! Yrotation` channels in degrees, the order most mocap tools expect; roots
+//! (and any joint whose location is animated) get position channels too.
+//! BVH is Y-up, so the file is converted from Blender's Z-up on the way out
+//! and back on the way in: the [`Bvh`] API itself works in scene space.
+//! Scale, materials and visibility have no BVH equivalent and are dropped.
+
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::anim::ObjAnimData;
+use crate::math::{EulerOrder, Mat4, Quat};
+use crate::scene::{TransformError, TransformGraph, Vector3};
+
+/// Rotation order of the channels written: Z X Y in the file, so Y is
+/// applied first.
+const ROTATION_ORDER: EulerOrder = EulerOrder::Yxz;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhChannel {
+    Xposition,
+    Yposition,
+    Zposition,
+    Xrotation,
+    Yrotation,
+    Zrotation,
+}
+
+impl BvhChannel {
+    const POSITION: [BvhChannel; 3] = [BvhChannel::Xposition, BvhChannel::Yposition, BvhChannel::Zposition];
+    const ROTATION: [BvhChannel; 3] = [BvhChannel::Zrotation, BvhChannel::Xrotation, BvhChannel::Yrotation];
+
+    fn name(self) -> &'static str {
+        match self {
+            BvhChannel::Xposition => "Xposition",
+            BvhChannel::Yposition => "Yposition",
+            BvhChannel::Zposition => "Zposition",
+            BvhChannel::Xrotation => "Xrotation",
+            BvhChannel::Yrotation => "Yrotation",
+            BvhChannel::Zrotation => "Zrotation",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Self::POSITION, Self::ROTATION].concat().into_iter().find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The Y-up axis the channel moves or rotates along.
+    fn axis(self) -> usize {
+        match self {
+            BvhChannel::Xposition | BvhChannel::Xrotation => 0,
+            BvhChannel::Yposition | BvhChannel::Yrotation => 1,
+            BvhChannel::Zposition | BvhChannel::Zrotation => 2,
+        }
+    }
+
+    fn is_position(self) -> bool {
+        Self::POSITION.contains(&self)
+    }
+}
+
+/// One joint of the hierarchy, with its offset and end site in scene space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Rest position relative to the parent.
+    pub offset: Vector3,
+    pub channels: Vec<BvhChannel>,
+    /// Tip of a leaf joint, relative to it.
+    pub end_site: Option<Vector3>,
+}
+
+/// A BVH file: the joints in file order (parents before children) and the
+/// raw channel values of every frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bvh {
+    pub joints: Vec<Joint>,
+    /// Seconds per frame.
+    pub frame_time: f32,
+    /// Channel values per frame, in joint and channel order. Positions are
+    /// Y-up and rotations in degrees, as in the file.
+    pub frames: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BvhError {
+    /// Unexpected content at a 1-based line.
+    Syntax { line: usize, message: String },
+    /// The file ended before the motion data was complete.
+    UnexpectedEnd,
+    /// The motion section has no frames to play.
+    NoFrames,
+}
+
+impl fmt::Display for BvhError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BvhError::Syntax { line, message } => write!(f, "BVH line {}: {}", line, message),
+            BvhError::UnexpectedEnd => write!(f, "unexpected end of BVH file"),
+            BvhError::NoFrames => write!(f, "the BVH file has no frames"),
+        }
+    }
+}
+
+impl std::error::Error for BvhError {}
+
+impl From<BvhError> for std::io::Error {
+    fn from(err: BvhError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Writes `anim_data` as a BVH file, sampling frames `0..=frames` at `fps`.
+pub fn write_bvh(path: impl AsRef<Path>, anim_data: &[ObjAnimData], frames: i32, fps: u32) -> std::io::Result<()> {
+    let bvh = Bvh::from_anim(anim_data, frames, fps)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(bvh.to_string().as_bytes())?;
+    writer.flush()
+}
+
+impl Bvh {
+    /// Samples `anim_data` over frames `0..=frames`.
+    pub fn from_anim(anim_data: &[ObjAnimData], frames: i32, fps: u32) -> Result<Self, TransformError> {
+        let graph = TransformGraph::new(anim_data.iter().map(|d| (d.name.as_str(), d.parent.as_deref())))?;
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); anim_data.len()];
+        let mut roots = Vec::new();
+        for i in 0..anim_data.len() {
+            match graph.parent(i) {
+                Some(parent) => children[parent].push(i),
+                None => roots.push(i),
+            }
+        }
+
+        // Depth first, so every joint directly follows its parent's block
+        let mut order = Vec::with_capacity(anim_data.len());
+        let mut stack: Vec<usize> = roots.into_iter().rev().collect();
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            stack.extend(children[i].iter().rev());
+        }
+        let mut index = vec![0; anim_data.len()];
+        for (joint, &i) in order.iter().enumerate() {
+            index[i] = joint;
+        }
+
+        let states: Vec<Vec<_>> =
+            anim_data.iter().map(|data| (0..=frames).map(|f| data.state_at(f as f32)).collect()).collect();
+        let joints: Vec<Joint> = order
+            .iter()
+            .map(|&i| {
+                let data = &anim_data[i];
+                let rest = states[i][0].location;
+                let moves = states[i].iter().any(|s| (s.location - rest).length() > 1e-6);
+                let mut channels = Vec::new();
+                if graph.parent(i).is_none() || moves {
+                    channels.extend(BvhChannel::POSITION);
+                }
+                channels.extend(BvhChannel::ROTATION);
+                let end_site = children[i].is_empty().then(|| {
+                    let pivot = Vector3::new(data.pivot[0], data.pivot[1], data.pivot[2]);
+                    // The far end of a limb segment, or the top of anything else
+                    if pivot.length() > 0.0 {
+                        pivot * -2.0
+                    } else {
+                        Vector3::new(0.0, 0.0, data.scale[2] * 0.5)
+                    }
+                });
+                Joint {
+                    name: data.name.clone(),
+                    parent: graph.parent(i).map(|p| index[p]),
+                    offset: rest,
+                    channels,
+                    end_site,
+                }
+            })
+            .collect();
+
+        let frames = (0..=frames as usize)
+            .map(|frame| {
+                let mut values = Vec::new();
+                for (joint, &i) in joints.iter().zip(&order) {
+                    let state = &states[i][frame];
+                    let location = y_up(state.location);
+                    let rotation = Mat4::from_quat(quat_y_up(Quat::from_euler(state.rotation, state.rotation_order)))
+                        .to_euler(ROTATION_ORDER);
+                    let rotation = [rotation.x, rotation.y, rotation.z];
+                    values.extend(joint.channels.iter().map(|c| {
+                        if c.is_position() {
+                            location[c.axis()]
+                        } else {
+                            rotation[c.axis()].to_degrees()
+                        }
+                    }));
+                }
+                values
+            })
+            .collect();
+
+        Ok(Self { joints, frame_time: 1.0 / fps as f32, frames })
+    }
+
+    /// Parses the text of a BVH file.
+    pub fn parse(text: &str) -> Result<Self, BvhError> {
+        let mut parser = Parser::new(text);
+        parser.expect("HIERARCHY")?;
+        let mut joints = Vec::new();
+        while parser.peek() == Some("ROOT") {
+            parser.next()?;
+            parser.joint(&mut joints, None)?;
+        }
+        if joints.is_empty() {
+            return Err(parser.error("expected ROOT"));
+        }
+
+        parser.expect("MOTION")?;
+        parser.expect("Frames:")?;
+        let count = parser.number::<usize>()?;
+        if count == 0 {
+            return Err(BvhError::NoFrames);
+        }
+        parser.expect("Frame")?;
+        parser.expect("Time:")?;
+        let frame_time = parser.number::<f32>()?;
+        let width: usize = joints.iter().map(|j: &Joint| j.channels.len()).sum();
+        let frames = (0..count)
+            .map(|_| (0..width).map(|_| parser.number::<f32>()).collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { joints, frame_time, frames })
+    }
+
+    /// Index of the first value of `joint` in each frame.
+    fn channel_start(&self, joint: usize) -> usize {
+        self.joints[..joint].iter().map(|j| j.channels.len()).sum()
+    }
+
+    /// Location and rotation of `joint` relative to its parent at `frame`,
+    /// in scene space. Joints without position channels sit at their offset.
+    pub fn local_pose(&self, frame: usize, joint: usize) -> (Vector3, Quat) {
+        let start = self.channel_start(joint);
+        let values = &self.frames[frame][start..start + self.joints[joint].channels.len()];
+        let mut location = y_up(self.joints[joint].offset);
+        // Rotations compose in channel order, the first one outermost
+        let mut rotation = Quat::IDENTITY;
+        for (&channel, &value) in self.joints[joint].channels.iter().zip(values) {
+            if channel.is_position() {
+                location[channel.axis()] = value;
+            } else {
+                let mut axis = [0.0; 3];
+                axis[channel.axis()] = 1.0;
+                let axis = Vector3::new(axis[0], axis[1], axis[2]);
+                rotation = rotation * Quat::from_axis_angle(axis, value.to_radians());
+            }
+        }
+        (z_up(location), quat_z_up(rotation))
+    }
+}
+
+impl fmt::Display for Bvh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::from("HIERARCHY\n");
+        let mut open: Vec<usize> = Vec::new();
+        for (i, joint) in self.joints.iter().enumerate() {
+            // Close the blocks of joints that aren't ancestors of this one
+            while open.last().is_some_and(|&top| Some(top) != joint.parent) {
+                open.pop();
+                writeln!(out, "{}}}", "\t".repeat(open.len()))?;
+            }
+            let indent = "\t".repeat(open.len());
+            let keyword = if joint.parent.is_some() { "JOINT" } else { "ROOT" };
+            writeln!(out, "{}{} {}", indent, keyword, joint.name)?;
+            writeln!(out, "{}{{", indent)?;
+            let [x, y, z] = y_up(joint.offset);
+            writeln!(out, "{}\tOFFSET {:.6} {:.6} {:.6}", indent, x, y, z)?;
+            let names: Vec<&str> = joint.channels.iter().map(|c| c.name()).collect();
+            writeln!(out, "{}\tCHANNELS {} {}", indent, names.len(), names.join(" "))?;
+            if let Some(end) = joint.end_site {
+                let [x, y, z] = y_up(end);
+                writeln!(out, "{}\tEnd Site\n{}\t{{", indent, indent)?;
+                writeln!(out, "{}\t\tOFFSET {:.6} {:.6} {:.6}", indent, x, y, z)?;
+                writeln!(out, "{}\t}}", indent)?;
+            }
+            open.push(i);
+        }
+        while open.pop().is_some() {
+            writeln!(out, "{}}}", "\t".repeat(open.len()))?;
+        }
+
+        writeln!(out, "MOTION")?;
+        writeln!(out, "Frames: {}", self.frames.len())?;
+        writeln!(out, "Frame Time: {:.6}", self.frame_time)?;
+        for values in &self.frames {
+            let line: Vec<String> = values.iter().map(|v| format!("{:.6}", v)).collect();
+            writeln!(out, "{}", line.join(" "))?;
+        }
+        f.write_str(&out)
+    }
+}
+
+/// Whitespace separated tokens with their line numbers.
+struct Parser<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        let tokens = text
+            .lines()
+            .enumerate()
+            .flat_map(|(line, content)| content.split_whitespace().map(move |t| (line + 1, t)))
+            .collect();
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|&(_, t)| t)
+    }
+
+    fn next(&mut self) -> Result<&'a str, BvhError> {
+        let &(_, token) = self.tokens.get(self.pos).ok_or(BvhError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error(&self, message: impl Into<String>) -> BvhError {
+        match self.tokens.get(self.pos) {
+            Some(&(line, _)) => BvhError::Syntax { line, message: message.into() },
+            None => BvhError::UnexpectedEnd,
+        }
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), BvhError> {
+        match self.peek() {
+            Some(token) if token.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => Err(self.error(format!("expected {} (got {})", keyword, token))),
+            None => Err(BvhError::UnexpectedEnd),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, BvhError> {
+        let token = self.peek().ok_or(BvhError::UnexpectedEnd)?;
+        let value = token.parse().map_err(|_| self.error(format!("expected a number (got {})", token)))?;
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn vector(&mut self) -> Result<Vector3, BvhError> {
+        let (x, y, z) = (self.number()?, self.number()?, self.number()?);
+        Ok(z_up([x, y, z]))
+    }
+
+    /// The rest of the current line, for names that may contain spaces.
+    fn name(&mut self) -> Result<String, BvhError> {
+        let &(line, _) = self.tokens.get(self.pos).ok_or(BvhError::UnexpectedEnd)?;
+        let mut words = Vec::new();
+        while let Some(&(l, token)) = self.tokens.get(self.pos) {
+            if l != line {
+                break;
+            }
+            words.push(token);
+            self.pos += 1;
+        }
+        Ok(words.join(" "))
+    }
+
+    /// A joint block after its `ROOT` or `JOINT` keyword, followed by its children.
+    fn joint(&mut self, joints: &mut Vec<Joint>, parent: Option<usize>) -> Result<(), BvhError> {
+        let name = self.name()?;
+        self.expect("{")?;
+        self.expect("OFFSET")?;
+        let offset = self.vector()?;
+        let mut channels = Vec::new();
+        if self.peek() == Some("CHANNELS") {
+            self.next()?;
+            let count = self.number::<usize>()?;
+            for _ in 0..count {
+                let token = self.peek().ok_or(BvhError::UnexpectedEnd)?;
+                let channel =
+                    BvhChannel::parse(token).ok_or_else(|| self.error(format!("unknown channel {}", token)))?;
+                channels.push(channel);
+                self.pos += 1;
+            }
+        }
+        let index = joints.len();
+        joints.push(Joint { name, parent, offset, channels, end_site: None });
+
+        loop {
+            match self.next()? {
+                "JOINT" => self.joint(joints, Some(index))?,
+                "End" => {
+                    self.expect("Site")?;
+                    self.expect("{")?;
+                    self.expect("OFFSET")?;
+                    joints[index].end_site = Some(self.vector()?);
+                    self.expect("}")?;
+                }
+                "}" => return Ok(()),
+                token => {
+                    self.pos -= 1;
+                    return Err(self.error(format!("unexpected {}", token)));
+                }
+            }
+        }
+    }
+}
+
+/// Converts a Blender (Z-up) vector to BVH (Y-up).
+fn y_up(v: Vector3) -> [f32; 3] {
+    // Subtracting from zero keeps `-0.0` out of the file
+    [v.x, v.z, 0.0 - v.y]
+}
+
+fn z_up(v: [f32; 3]) -> Vector3 {
+    Vector3::new(v[0], -v[2], v[1])
+}
+
+fn quat_y_up(q: Quat) -> Quat {
+    Quat { x: q.x, y: q.z, z: -q.y, w: q.w }
+}
+
+fn quat_z_up(q: Quat) -> Quat {
+    Quat { x: q.x, y: -q.z, z: q.y, w: q.w }
+}
@@ -0,0 +1,561 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::audio::effects::EffectsConfig;
+use crate::audio::wav::{SampleFormat, WavSpec};
+use crate::gait::{GaitKind, GaitParams, GaitSegment, GaitTimeline};
+use crate::path::{Path as RootPath, PathError, PathKind, Speed};
+use crate::scene::Vector3;
+use crate::tempo::{TempoError, TempoMap, TimeSignature};
+
+/// Default location of the project configuration file.
+pub const CONFIG_FILE: &str = "ghostrender.toml";
+
+/// Most channels `audio.channels` takes, the speaker positions WAV defines.
+const MAX_CHANNELS: u16 = 18;
+/// Highest `audio.sample_rate`, in Hz.
+const MAX_SAMPLE_RATE: u32 = 384_000;
+
+/// Project configuration, usually loaded from `ghostrender.toml`.
+///
+/// Every section and field is optional; missing values fall back to the
+/// defaults below.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Seeds all randomness, so identical configs produce identical outputs.
+    pub seed: u64,
+    pub animation: AnimationConfig,
+    pub render: RenderConfig,
+    pub output: OutputConfig,
+    pub audio: AudioConfig,
+    pub tempo: TempoConfig,
+    pub preview: PreviewConfig,
+    pub path: PathConfig,
+    /// Gaits over the course of the render. A single walk when empty.
+    pub gaits: Vec<GaitConfig>,
+    pub mocap: MocapConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationConfig {
+    /// Last frame of the animation.
+    pub frames: i32,
+    pub fps: u32,
+    /// Maximum deviation allowed when fitting sampled curves to sparse keys.
+    pub tolerance: f32,
+    /// How the setup script builds the character in Blender.
+    pub rig: Rig,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rig {
+    /// One mesh object per body part, parented like the scene objects.
+    #[default]
+    Objects,
+    /// An armature with a bone per body part, deforming a single mesh.
+    Armature,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            frames: 1800, // 30 seconds at 60 FPS
+            fps: 60,
+            tolerance: 0.001,
+            rig: Rig::Objects,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// Number of parallel render processes.
+    pub chunks: i32,
+    /// Blender executable. Searched for in the usual locations when unset.
+    pub blender: Option<String>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self { chunks: 4, blender: None }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Generated Blender setup script.
+    pub script: String,
+    /// Animation data loaded by the setup script.
+    pub anim_data: String,
+    /// Scene saved by the setup script and loaded by the render workers.
+    pub blend_file: String,
+    /// Final concatenated video.
+    pub video: String,
+    /// Directory the rendered chunks and the concat script are written to.
+    pub chunks_dir: String,
+    /// glTF export written by the `export-gltf` stage.
+    pub gltf: String,
+    /// Motion capture export written by the `export-bvh` stage.
+    pub bvh: String,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            script: "setup_scene.py".to_string(),
+            anim_data: "anim_data.json".to_string(),
+            blend_file: "scene.blend".to_string(),
+            video: "animation_output.mp4".to_string(),
+            chunks_dir: "chunks".to_string(),
+            gltf: "animation.glb".to_string(),
+            bvh: "animation.bvh".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Where the synthesized soundtrack is written.
+    pub file: String,
+    pub duration_secs: u32,
+    pub sample_rate: u32,
+    /// Every channel gets the same mix.
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+    /// JSON pattern file played by the synth. A built-in groove when unset.
+    pub pattern: Option<String>,
+    /// Your own WAV track, used instead of the synth. Its detected beats and
+    /// bass drive the animation in place of `tempo`.
+    pub input: Option<String>,
+    /// The synth's master bus.
+    pub effects: EffectsConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            file: "audio.wav".to_string(),
+            duration_secs: 30,
+            sample_rate: 44100,
+            channels: 1,
+            sample_format: SampleFormat::I16,
+            pattern: None,
+            input: None,
+            effects: EffectsConfig::default(),
+        }
+    }
+}
+
+impl AudioConfig {
+    /// The soundtrack of the video: `input`, or else the synthesized `file`.
+    pub fn track(&self) -> &str {
+        self.input.as_deref().unwrap_or(&self.file)
+    }
+
+    /// Layout of the synthesized `file`.
+    pub fn spec(&self) -> WavSpec {
+        WavSpec { channels: self.channels, sample_rate: self.sample_rate, format: self.sample_format }
+    }
+}
+
+/// The soundtrack's tempo, shared by the synth and the animation.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TempoConfig {
+    /// Starting tempo in beats per minute.
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+    /// The note value of one beat (4 for quarter notes).
+    pub beat_unit: u32,
+    /// `[beat, bpm]` tempo changes, sorted by beat.
+    pub changes: Vec<[f64; 2]>,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        Self { bpm: 120.0, beats_per_bar: 4, beat_unit: 4, changes: Vec::new() }
+    }
+}
+
+impl TempoConfig {
+    pub fn map(&self) -> Result<TempoMap, TempoError> {
+        let changes: Vec<(f64, f64)> = self.changes.iter().map(|c| (c[0], c[1])).collect();
+        let signature = TimeSignature { beats_per_bar: self.beats_per_bar, beat_unit: self.beat_unit };
+        TempoMap::new(self.bpm, &changes, signature)
+    }
+}
+
+/// Software preview written by `--preview` instead of the Blender render.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Directory for the numbered PNG frames.
+    pub frames_dir: String,
+    /// Animated PNG of the whole preview.
+    pub animation: String,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self { width: 480, height: 270, frames_dir: "preview".to_string(), animation: "preview.png".to_string() }
+    }
+}
+
+/// A motion capture clip rendered instead of the procedural character.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MocapConfig {
+    /// BVH file. The gaits are used when unset. The clip keeps its own root
+    /// travel, so the path only places its start and heading.
+    pub file: Option<String>,
+    /// Scene units per BVH unit, e.g. 0.01 for clips in centimeters.
+    pub scale: f32,
+}
+
+impl Default for MocapConfig {
+    fn default() -> Self {
+        Self { file: None, scale: 1.0 }
+    }
+}
+
+/// The path the character's root walks along.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+    pub kind: PathKind,
+    /// Control points, in scene units. The character starts at the first one.
+    pub points: Vec<[f32; 3]>,
+    /// Walking speed in scene units per second. The root stops while a gait
+    /// that stays in place (`idle`, `jump`, `dance`) plays.
+    pub speed: f32,
+    /// `[frame, speed]` keys, interpolated linearly. Replace `speed` when set.
+    pub speed_keys: Vec<[f32; 2]>,
+    /// Follow the tempo instead, taking a step of the current gait on every
+    /// beat. Replaces `speed` and `speed_keys`.
+    pub beat_sync: bool,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        Self {
+            kind: PathKind::Polyline,
+            points: vec![[0.0, 0.0, 0.0], [0.0, -1000.0, 0.0]], // straight down the road
+            speed: 2.4,
+            speed_keys: Vec::new(),
+            beat_sync: false,
+        }
+    }
+}
+
+impl PathConfig {
+    pub fn path(&self) -> Result<RootPath, PathError> {
+        let points: Vec<Vector3> = self.points.iter().map(|p| Vector3::new(p[0], p[1], p[2])).collect();
+        RootPath::new(self.kind, &points)
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.speed_keys.is_empty() {
+            Speed::Constant(self.speed)
+        } else {
+            Speed::Keyed(self.speed_keys.iter().map(|k| (k[0], k[1])).collect())
+        }
+    }
+}
+
+/// One `[[gaits]]` entry. Unset parameters use the defaults of `kind`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GaitConfig {
+    pub kind: GaitKind,
+    /// First frame of the gait.
+    pub start: i32,
+    /// Crossfade from the previous gait, in frames.
+    pub blend: i32,
+    /// Distance per cycle (two steps) of `walk` and `run`.
+    pub stride: Option<f32>,
+    /// Peak arm rotation in radians.
+    pub arm_swing: Option<f32>,
+    /// Height the feet are lifted to when stepping, or tucked up when jumping.
+    pub foot_lift: Option<f32>,
+    /// Vertical bob of the torso, or the jump height.
+    pub bob: Option<f32>,
+    /// Cycles per second of `idle` and `jump`. `dance` follows the tempo.
+    pub cadence: Option<f32>,
+}
+
+impl GaitConfig {
+    pub fn params(&self) -> GaitParams {
+        let defaults = GaitParams::for_kind(self.kind);
+        GaitParams {
+            stride: self.stride.unwrap_or(defaults.stride),
+            arm_swing: self.arm_swing.unwrap_or(defaults.arm_swing),
+            foot_lift: self.foot_lift.unwrap_or(defaults.foot_lift),
+            bob: self.bob.unwrap_or(defaults.bob),
+            cadence: self.cadence.unwrap_or(defaults.cadence),
+        }
+    }
+}
+
+/// Values given on the command line, applied on top of the file.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub seed: Option<u64>,
+    pub frames: Option<i32>,
+    pub chunks: Option<i32>,
+    pub fps: Option<u32>,
+    pub output: Option<String>,
+    pub blender: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "failed to parse config: {}", e),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Parse(e) => Some(e),
+            ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<ConfigError> for std::io::Error {
+    fn from(e: ConfigError) -> Self {
+        match e {
+            ConfigError::Io(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
+
+impl Config {
+    /// The `gaits` as a timeline, or a walk when there are none.
+    pub fn gait_timeline(&self) -> GaitTimeline {
+        let gaits = if self.gaits.is_empty() { vec![GaitConfig::default()] } else { self.gaits.clone() };
+        GaitTimeline::new(
+            gaits
+                .iter()
+                .map(|g| GaitSegment { start: g.start, blend: g.blend, gait: g.params().gait(g.kind) })
+                .collect(),
+        )
+    }
+
+    /// Parses and validates a TOML configuration file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&text)
+    }
+
+    /// Like [`Config::load`], but returns the defaults when the file does not exist.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Applies command line overrides and re-validates the result.
+    pub fn apply(&mut self, overrides: &Overrides) -> Result<(), ConfigError> {
+        if let Some(seed) = overrides.seed {
+            self.seed = seed;
+        }
+        if let Some(frames) = overrides.frames {
+            self.animation.frames = frames;
+        }
+        if let Some(chunks) = overrides.chunks {
+            self.render.chunks = chunks;
+        }
+        if let Some(fps) = overrides.fps {
+            self.animation.fps = fps;
+        }
+        if let Some(output) = &overrides.output {
+            self.output.video = output.clone();
+        }
+        if let Some(blender) = &overrides.blender {
+            self.render.blender = Some(blender.clone());
+        }
+        self.validate()
+    }
+
+    /// Checks that the values are consistent with each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        let frames = self.animation.frames;
+        let fps = self.animation.fps;
+
+        if frames <= 0 {
+            return invalid(format!("animation.frames must be positive (got {})", frames));
+        }
+        if fps == 0 {
+            return invalid("animation.fps must be positive".to_string());
+        }
+        if self.animation.tolerance.is_nan() || self.animation.tolerance < 0.0 {
+            return invalid(format!("animation.tolerance must not be negative (got {})", self.animation.tolerance));
+        }
+        if self.render.chunks <= 0 {
+            return invalid(format!("render.chunks must be positive (got {})", self.render.chunks));
+        }
+        if self.render.chunks > frames {
+            return invalid(format!("render.chunks ({}) exceeds animation.frames ({})", self.render.chunks, frames));
+        }
+        if self.preview.width == 0 || self.preview.height == 0 {
+            return invalid(format!(
+                "preview size must be positive (got {}x{})",
+                self.preview.width, self.preview.height
+            ));
+        }
+        if self.mocap.scale.is_nan() || self.mocap.scale <= 0.0 {
+            return invalid(format!("mocap.scale must be positive (got {})", self.mocap.scale));
+        }
+        if self.audio.sample_rate == 0 || self.audio.channels == 0 {
+            return invalid(format!(
+                "audio needs a sample rate and channels (got {} Hz, {} channels)",
+                self.audio.sample_rate, self.audio.channels
+            ));
+        }
+        if self.audio.channels > MAX_CHANNELS {
+            return invalid(format!("audio.channels must be at most {} (got {})", MAX_CHANNELS, self.audio.channels));
+        }
+        if self.audio.sample_rate > MAX_SAMPLE_RATE {
+            return invalid(format!(
+                "audio.sample_rate must be at most {} Hz (got {})",
+                MAX_SAMPLE_RATE, self.audio.sample_rate
+            ));
+        }
+        if let Err(e) = self.audio.effects.validate() {
+            return invalid(format!("audio.effects.{}", e));
+        }
+        if let Err(e) = self.tempo.map() {
+            return invalid(format!("tempo: {}", e));
+        }
+        if let Err(e) = self.path.path() {
+            return invalid(format!("path: {}", e));
+        }
+        for (i, gait) in self.gaits.iter().enumerate() {
+            let params = gait.params();
+            if params.stride.is_nan() || params.stride <= 0.0 {
+                return invalid(format!("gaits[{}].stride must be positive (got {})", i, params.stride));
+            }
+            if params.cadence.is_nan() || params.cadence < 0.0 {
+                return invalid(format!("gaits[{}].cadence must not be negative (got {})", i, params.cadence));
+            }
+            if gait.blend < 0 {
+                return invalid(format!("gaits[{}].blend must not be negative (got {})", i, gait.blend));
+            }
+        }
+        if self.gaits.windows(2).any(|g| g[1].start <= g[0].start) {
+            return invalid("gaits must be sorted by start frame".to_string());
+        }
+        let negative = |speed: f32| speed.is_nan() || speed < 0.0;
+        if negative(self.path.speed) || self.path.speed_keys.iter().any(|k| negative(k[1])) {
+            return invalid("path speeds must not be negative".to_string());
+        }
+        if self.path.speed_keys.windows(2).any(|k| k[1][0].partial_cmp(&k[0][0]) != Some(Ordering::Greater)) {
+            return invalid("path.speed_keys must be sorted by frame".to_string());
+        }
+        if self.audio.input.is_none() && u64::from(self.audio.duration_secs) * u64::from(fps) < frames as u64 {
+            return invalid(format!(
+                "audio.duration_secs ({}s) is shorter than the animation ({} frames at {} fps = {:.2}s)",
+                self.audio.duration_secs,
+                frames,
+                fps,
+                frames as f64 / f64::from(fps)
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejects(text: &str, what: &str) {
+        match Config::from_toml(text) {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.contains(what), "{:?} doesn't mention {}", msg, what),
+            other => panic!("expected {} to be rejected, got {:?}", what, other),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+        Config::from_toml("").unwrap();
+    }
+
+    #[test]
+    fn inconsistent_values_are_rejected() {
+        rejects("[animation]\nframes = 0", "animation.frames");
+        rejects("[animation]\nfps = 0", "animation.fps");
+        rejects("[render]\nchunks = 0", "render.chunks");
+        rejects("[animation]\nframes = 10\n[render]\nchunks = 11", "exceeds animation.frames");
+        rejects("[animation]\nframes = 600\nfps = 60\n[audio]\nduration_secs = 9", "audio.duration_secs");
+        rejects("[audio]\nchannels = 0", "sample rate and channels");
+        rejects("[audio]\nchannels = 19", "audio.channels");
+        rejects("[audio]\nsample_rate = 400000", "audio.sample_rate");
+        rejects("[[gaits]]\nkind = \"walk\"\nstart = 30\n[[gaits]]\nkind = \"run\"\nstart = 10", "sorted by start");
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for text in ["[animation]\nframe = 10", "[render]\nthreads = 4", "[video]\nfile = \"a.mp4\""] {
+            assert!(matches!(Config::from_toml(text), Err(ConfigError::Parse(_))), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_file() {
+        let mut config = Config::from_toml("[animation]\nframes = 120\nfps = 30\n[render]\nchunks = 2").unwrap();
+        let overrides = Overrides {
+            frames: Some(90),
+            chunks: Some(3),
+            fps: None,
+            output: Some("out.mp4".to_string()),
+            blender: Some("/opt/blender".to_string()),
+            seed: Some(7),
+        };
+        config.apply(&overrides).unwrap();
+        assert_eq!((config.animation.frames, config.animation.fps, config.render.chunks), (90, 30, 3));
+        assert_eq!(config.output.video, "out.mp4");
+        assert_eq!(config.render.blender.as_deref(), Some("/opt/blender"));
+        assert_eq!(config.seed, 7);
+
+        // The result is validated again
+        let overrides = Overrides { chunks: Some(200), ..Overrides::default() };
+        assert!(matches!(config.apply(&overrides), Err(ConfigError::Invalid(_))));
+    }
+}
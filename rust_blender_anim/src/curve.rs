@@ -0,0 +1,305 @@
+//! Sparse animation curves (Blender F-curve style) and fitting of densely
+//! sampled values down to a few keys.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Interpolation {
+    /// Hold the value until the next key.
+    Constant,
+    Linear,
+    /// Cubic Bezier using the keys' handles.
+    #[default]
+    Bezier,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct CurveKey {
+    pub frame: f32,
+    pub value: f32,
+    /// Interpolation from this key to the next one.
+    pub interpolation: Interpolation,
+    /// Bezier handles as `[frame, value]`, only used by `Bezier` segments.
+    pub handle_left: [f32; 2],
+    pub handle_right: [f32; 2],
+}
+
+impl CurveKey {
+    pub fn new(frame: f32, value: f32, interpolation: Interpolation) -> Self {
+        Self { frame, value, interpolation, handle_left: [frame, value], handle_right: [frame, value] }
+    }
+}
+
+/// A single animated value, as a list of keys sorted by frame.
+///
+/// Serialized as flat columns (`co`, `handle_left`, `handle_right` as
+/// `[frame, value, frame, value, ...]`, plus `interpolation`) that Blender's
+/// `keyframe_points.foreach_set` accepts directly.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FCurve {
+    pub keys: Vec<CurveKey>,
+}
+
+impl Serialize for FCurve {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Columns {
+            co: Vec<f32>,
+            handle_left: Vec<f32>,
+            handle_right: Vec<f32>,
+            interpolation: Vec<Interpolation>,
+        }
+
+        let flat = |f: fn(&CurveKey) -> [f32; 2]| self.keys.iter().flat_map(f).collect();
+        Columns {
+            co: flat(|k| [k.frame, k.value]),
+            handle_left: flat(|k| k.handle_left),
+            handle_right: flat(|k| k.handle_right),
+            interpolation: self.keys.iter().map(|k| k.interpolation).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl FCurve {
+    /// Builds a curve from `keys` (sorted by frame), giving `Bezier` keys
+    /// automatic clamped handles.
+    pub fn new(keys: Vec<CurveKey>) -> Self {
+        let mut curve = Self { keys };
+        curve.auto_handles();
+        curve
+    }
+
+    /// Sets smooth handles on every key: the slope is taken from the
+    /// neighbouring keys and flattened at local extremes so the curve
+    /// doesn't overshoot. Handles reach a third of the way to each neighbour.
+    pub fn auto_handles(&mut self) {
+        let n = self.keys.len();
+        for i in 0..n {
+            let key = self.keys[i];
+            let prev = if i > 0 { self.keys[i - 1] } else { key };
+            let next = if i + 1 < n { self.keys[i + 1] } else { key };
+
+            let extreme = (key.value - prev.value) * (next.value - key.value) <= 0.0;
+            let slope = if extreme || next.frame == prev.frame {
+                0.0
+            } else {
+                (next.value - prev.value) / (next.frame - prev.frame)
+            };
+
+            let left = (key.frame - prev.frame) / 3.0;
+            let right = (next.frame - key.frame) / 3.0;
+            self.keys[i].handle_left = [key.frame - left, key.value - slope * left];
+            self.keys[i].handle_right = [key.frame + right, key.value + slope * right];
+        }
+    }
+
+    /// Value at `frame`. The first and last keys are held outside the curve.
+    pub fn evaluate(&self, frame: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if frame <= first.frame {
+            return first.value;
+        }
+        if frame >= last.frame {
+            return last.value;
+        }
+
+        // Index of the last key at or before `frame`
+        let i = self.keys.partition_point(|k| k.frame <= frame) - 1;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        match a.interpolation {
+            Interpolation::Constant => a.value,
+            Interpolation::Linear => {
+                let t = (frame - a.frame) / (b.frame - a.frame);
+                a.value + (b.value - a.value) * t
+            }
+            Interpolation::Bezier => {
+                let xs = [a.frame, a.handle_right[0], b.handle_left[0], b.frame];
+                let ys = [a.value, a.handle_right[1], b.handle_left[1], b.value];
+                // The handles keep x(t) monotonic, so bisect for x(t) = frame
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = 0.5 * (lo + hi);
+                    if cubic_bezier(xs, mid) < frame {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                cubic_bezier(ys, 0.5 * (lo + hi))
+            }
+        }
+    }
+
+    /// Reduces one sample per frame (starting at `start_frame`) to Bezier
+    /// keys that stay within `tolerance` of every sample.
+    ///
+    /// Segments are cubic Hermite splines using the sampled slopes at their
+    /// ends; each segment is grown as far as the tolerance allows.
+    pub fn fit(samples: &[f32], start_frame: f32, tolerance: f32) -> Self {
+        let slopes = sample_slopes(samples);
+        let fits = |i: usize, j: usize| {
+            let len = (j - i) as f32;
+            (i + 1..j).all(|k| {
+                let s = (k - i) as f32 / len;
+                let h = hermite(samples[i], slopes[i] * len, samples[j], slopes[j] * len, s);
+                (h - samples[k]).abs() <= tolerance
+            })
+        };
+
+        let ends = greedy_segments(samples.len(), fits);
+        let mut keys: Vec<CurveKey> =
+            ends.iter().map(|&i| CurveKey::new(start_frame + i as f32, samples[i], Interpolation::Bezier)).collect();
+
+        // Hermite tangents become handles a third of the way along each segment
+        for w in 0..keys.len().saturating_sub(1) {
+            let (i, j) = (ends[w], ends[w + 1]);
+            let third = (j - i) as f32 / 3.0;
+            let (a, b) = (keys[w], keys[w + 1]);
+            keys[w].handle_right = [a.frame + third, a.value + slopes[i] * third];
+            keys[w + 1].handle_left = [b.frame - third, b.value - slopes[j] * third];
+        }
+        if let Some(first) = keys.first_mut() {
+            first.handle_left = mirror(first.handle_right, first.frame, first.value);
+        }
+        if let Some(last) = keys.last_mut() {
+            last.handle_right = mirror(last.handle_left, last.frame, last.value);
+        }
+
+        Self { keys }
+    }
+
+    /// Stepped keys wherever the value changes by more than `tolerance`.
+    pub fn fit_constant(samples: &[f32], start_frame: f32, tolerance: f32) -> Self {
+        let mut keys: Vec<CurveKey> = Vec::new();
+        for (i, &v) in samples.iter().enumerate() {
+            if keys.last().is_none_or(|k| (k.value - v).abs() > tolerance) {
+                keys.push(CurveKey::new(start_frame + i as f32, v, Interpolation::Constant));
+            }
+        }
+        Self { keys }
+    }
+}
+
+fn cubic_bezier(p: [f32; 4], t: f32) -> f32 {
+    let u = 1.0 - t;
+    u * u * u * p[0] + 3.0 * u * u * t * p[1] + 3.0 * u * t * t * p[2] + t * t * t * p[3]
+}
+
+fn hermite(p0: f32, m0: f32, p1: f32, m1: f32, s: f32) -> f32 {
+    let s2 = s * s;
+    let s3 = s2 * s;
+    (2.0 * s3 - 3.0 * s2 + 1.0) * p0 + (s3 - 2.0 * s2 + s) * m0 + (-2.0 * s3 + 3.0 * s2) * p1 + (s3 - s2) * m1
+}
+
+fn mirror(handle: [f32; 2], frame: f32, value: f32) -> [f32; 2] {
+    [2.0 * frame - handle[0], 2.0 * value - handle[1]]
+}
+
+/// Per-frame slope of the samples (central differences, one-sided at the ends).
+fn sample_slopes(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    (0..n)
+        .map(|i| match (i.checked_sub(1), (i + 1 < n).then_some(i + 1)) {
+            (Some(p), Some(q)) => (samples[q] - samples[p]) / 2.0,
+            (None, Some(q)) => samples[q] - samples[i],
+            (Some(p), None) => samples[i] - samples[p],
+            (None, None) => 0.0,
+        })
+        .collect()
+}
+
+/// Splits `0..n` into segments, each as long as `fits(start, end)` allows,
+/// and returns the segment end points (always including `0` and `n - 1`).
+///
+/// The search gallops forward and then bisects, so long flat stretches cost
+/// O(log n) fit checks instead of O(n).
+fn greedy_segments(n: usize, fits: impl Fn(usize, usize) -> bool) -> Vec<usize> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut ends = vec![0];
+    let mut i = 0;
+    while i < n - 1 {
+        // Adjacent samples always fit
+        let mut good = i + 1;
+        let mut step = 1;
+        let mut bad = None;
+        while good < n - 1 {
+            let candidate = (i + step * 2).min(n - 1);
+            if fits(i, candidate) {
+                good = candidate;
+                step *= 2;
+            } else {
+                bad = Some(candidate);
+                break;
+            }
+        }
+        if let Some(mut bad) = bad {
+            while bad - good > 1 {
+                let mid = (good + bad) / 2;
+                if fits(i, mid) {
+                    good = mid;
+                } else {
+                    bad = mid;
+                }
+            }
+        }
+        ends.push(good);
+        i = good;
+    }
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest distance between the curve and the samples, at the sample frames.
+    fn max_error(curve: &FCurve, samples: &[f32], start_frame: f32) -> f32 {
+        samples.iter().enumerate().map(|(i, &v)| (curve.evaluate(start_frame + i as f32) - v).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn fit_stays_within_tolerance() {
+        let wave = |x: f32| (x * 0.1).sin() * 2.0 + (x * 0.37).cos() * 0.3;
+        let samples: Vec<f32> = (0..240).map(|i| wave(i as f32)).collect();
+        for tolerance in [0.1, 0.01, 0.001] {
+            let curve = FCurve::fit(&samples, 10.0, tolerance);
+            assert!(curve.keys.len() < samples.len());
+            // Bisecting the Bezier for the frame adds a little error of its own
+            let error = max_error(&curve, &samples, 10.0);
+            assert!(error <= tolerance + 1e-4, "error {} over tolerance {}", error, tolerance);
+        }
+    }
+
+    #[test]
+    fn fit_collapses_constant_and_linear_input() {
+        let constant = vec![3.5; 100];
+        let curve = FCurve::fit(&constant, 0.0, 0.001);
+        assert_eq!(curve.keys.len(), 2);
+        assert!(max_error(&curve, &constant, 0.0) <= 1e-4);
+
+        let linear: Vec<f32> = (0..100).map(|i| 1.0 - 0.25 * i as f32).collect();
+        let curve = FCurve::fit(&linear, 0.0, 0.001);
+        assert_eq!(curve.keys.len(), 2);
+        assert!(max_error(&curve, &linear, 0.0) <= 1e-3);
+
+        assert_eq!(FCurve::fit(&[1.0], 0.0, 0.001).keys.len(), 1);
+        assert!(FCurve::fit(&[], 0.0, 0.001).keys.is_empty());
+    }
+
+    #[test]
+    fn greedy_segments_cover_the_samples() {
+        // Segments of at most 5 samples
+        let ends = greedy_segments(23, |i, j| j - i <= 5);
+        assert_eq!(ends.first(), Some(&0));
+        assert_eq!(ends.last(), Some(&22));
+        assert!(ends.windows(2).all(|w| w[0] < w[1] && w[1] - w[0] <= 5));
+        assert_eq!(ends.len(), 6);
+    }
+}
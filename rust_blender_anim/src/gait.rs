@@ -0,0 +1,464 @@
+//! Gait generators: procedural poses for the character, blended over time.
+//!
+//! A [`Gait`] maps a phase (in cycles) to a [`Pose`]. The [`GaitTimeline`]
+//! switches between gaits with crossfades and advances one shared phase, so
+//! the legs stay in step while blending from one gait to the next.
+//!
+//! Legs are posed by where the feet go rather than by joint angles: the
+//! scene solves them with [`crate::ik`], so planted feet stay put on the
+//! ground while the body moves over them.
+
+use std::f32::consts::{PI, TAU};
+
+use serde::Deserialize;
+
+use crate::scene::{Vector3, HIP};
+
+/// Joint rotations, offsets and foot targets of the character, relative to
+/// its rest pose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    /// Torso height above its rest position.
+    pub height: f32,
+    pub torso: Vector3,
+    pub head: Vector3,
+    /// Left and right arm rotations: the direction from shoulder to hand.
+    pub arms: [Vector3; 2],
+    /// Distance from shoulder to hand, as a fraction of the arm's length.
+    /// Shorter bends the elbows.
+    pub reach: f32,
+    /// Left and right foot targets relative to the root, which stands on
+    /// the ground (`z = 0`) and faces -Y.
+    pub feet: [Vector3; 2],
+}
+
+impl Default for Pose {
+    /// Standing straight, feet under the hips.
+    fn default() -> Self {
+        Pose {
+            height: 0.0,
+            torso: Vector3::default(),
+            head: Vector3::default(),
+            arms: [Vector3::default(); 2],
+            reach: 0.95,
+            feet: [Vector3::new(HIP.x, 0.0, 0.0), Vector3::new(-HIP.x, 0.0, 0.0)],
+        }
+    }
+}
+
+impl Pose {
+    /// Interpolates every component from `self` (at 0) to `other` (at 1).
+    pub fn lerp(&self, other: &Pose, t: f32) -> Pose {
+        let mix = |a: Vector3, b: Vector3| a + (b - a) * t;
+        Pose {
+            height: self.height + (other.height - self.height) * t,
+            torso: mix(self.torso, other.torso),
+            head: mix(self.head, other.head),
+            arms: [mix(self.arms[0], other.arms[0]), mix(self.arms[1], other.arms[1])],
+            reach: self.reach + (other.reach - self.reach) * t,
+            feet: [mix(self.feet[0], other.feet[0]), mix(self.feet[1], other.feet[1])],
+        }
+    }
+}
+
+/// A procedural motion style.
+pub trait Gait {
+    /// Cycles per second while the root moves at `speed` units per second.
+    fn rate(&self, speed: f32) -> f32;
+
+    /// Distance the root covers per cycle, or 0 for gaits that stay in place.
+    fn stride(&self) -> f32;
+
+    /// The pose `phase` cycles into the gait, `beat` beats into the soundtrack.
+    fn pose(&self, phase: f32, beat: f32) -> Pose;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GaitKind {
+    #[default]
+    Walk,
+    Run,
+    Idle,
+    Jump,
+    Dance,
+}
+
+/// Shape of a gait. Not every gait uses every parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GaitParams {
+    /// Distance covered per cycle (two steps) by the moving gaits.
+    pub stride: f32,
+    /// Peak arm rotation in radians.
+    pub arm_swing: f32,
+    /// Height the feet are lifted to when stepping; how far they tuck up
+    /// in the air for [`Jump`].
+    pub foot_lift: f32,
+    /// Vertical bob of the torso; the jump height for [`Jump`].
+    pub bob: f32,
+    /// Cycles per second of the gaits that stay in place, except
+    /// [`DanceOnBeat`] which follows the tempo.
+    pub cadence: f32,
+}
+
+impl GaitParams {
+    pub fn for_kind(kind: GaitKind) -> Self {
+        match kind {
+            GaitKind::Walk => Self { stride: 2.4, arm_swing: 0.5, foot_lift: 0.2, bob: 0.05, cadence: 1.0 },
+            GaitKind::Run => Self { stride: 4.0, arm_swing: 0.9, foot_lift: 0.45, bob: 0.15, cadence: 1.5 },
+            GaitKind::Idle => Self { stride: 2.4, arm_swing: 0.05, foot_lift: 0.0, bob: 0.02, cadence: 0.25 },
+            GaitKind::Jump => Self { stride: 2.4, arm_swing: 2.5, foot_lift: 0.4, bob: 1.0, cadence: 0.75 },
+            GaitKind::Dance => Self { stride: 2.4, arm_swing: 2.2, foot_lift: 0.25, bob: 0.15, cadence: 1.0 },
+        }
+    }
+
+    pub fn gait(self, kind: GaitKind) -> Box<dyn Gait> {
+        match kind {
+            GaitKind::Walk => Box::new(Walk(self)),
+            GaitKind::Run => Box::new(Run(self)),
+            GaitKind::Idle => Box::new(Idle(self)),
+            GaitKind::Jump => Box::new(Jump(self)),
+            GaitKind::Dance => Box::new(DanceOnBeat(self)),
+        }
+    }
+}
+
+/// Each foot planted for most of the cycle, arms swinging opposite to the
+/// legs, advancing with the distance walked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Walk(pub GaitParams);
+
+impl Default for Walk {
+    fn default() -> Self {
+        Self(GaitParams::for_kind(GaitKind::Walk))
+    }
+}
+
+impl Gait for Walk {
+    fn rate(&self, speed: f32) -> f32 {
+        speed / self.0.stride
+    }
+
+    fn stride(&self) -> f32 {
+        self.0.stride
+    }
+
+    fn pose(&self, phase: f32, _beat: f32) -> Pose {
+        const STANCE: f32 = 0.6;
+        let p = &self.0;
+        let (feet, swing) = stepping(phase, p.stride, STANCE, p.foot_lift);
+        Pose {
+            // Highest over the planted foot
+            height: ((phase - STANCE * 0.5) * 2.0 * TAU).cos() * p.bob,
+            torso: Vector3::new(0.0, 0.0, swing * 0.1),
+            head: Vector3::new(0.0, 0.0, 0.0),
+            arms: [Vector3::new(-swing * p.arm_swing, 0.0, 0.0), Vector3::new(swing * p.arm_swing, 0.0, 0.0)],
+            reach: 0.95,
+            feet,
+        }
+    }
+}
+
+/// A faster, leaning gait with a flight phase on every step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Run(pub GaitParams);
+
+impl Default for Run {
+    fn default() -> Self {
+        Self(GaitParams::for_kind(GaitKind::Run))
+    }
+}
+
+impl Gait for Run {
+    fn rate(&self, speed: f32) -> f32 {
+        speed / self.0.stride
+    }
+
+    fn stride(&self) -> f32 {
+        self.0.stride
+    }
+
+    fn pose(&self, phase: f32, _beat: f32) -> Pose {
+        const STANCE: f32 = 0.35;
+        let p = &self.0;
+        let (feet, swing) = stepping(phase, p.stride, STANCE, p.foot_lift);
+        Pose {
+            // Highest between foot strikes
+            height: -((phase - STANCE * 0.5) * 2.0 * TAU).cos() * p.bob,
+            torso: Vector3::new(0.2, 0.0, swing * 0.15),
+            head: Vector3::new(-0.15, 0.0, 0.0),
+            arms: [Vector3::new(-swing * p.arm_swing, 0.0, 0.0), Vector3::new(swing * p.arm_swing, 0.0, 0.0)],
+            reach: 0.7,
+            feet,
+        }
+    }
+}
+
+/// Standing and breathing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Idle(pub GaitParams);
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self(GaitParams::for_kind(GaitKind::Idle))
+    }
+}
+
+impl Gait for Idle {
+    fn rate(&self, _speed: f32) -> f32 {
+        self.0.cadence
+    }
+
+    fn stride(&self) -> f32 {
+        0.0
+    }
+
+    fn pose(&self, phase: f32, _beat: f32) -> Pose {
+        let p = &self.0;
+        let breath = (phase * TAU).sin();
+        Pose {
+            height: breath * p.bob,
+            head: Vector3::new(breath * 0.03, 0.0, 0.0),
+            arms: [Vector3::new(0.0, -breath * p.arm_swing, 0.0), Vector3::new(0.0, breath * p.arm_swing, 0.0)],
+            ..Pose::default()
+        }
+    }
+}
+
+/// Repeated jumps: crouch, take off with the arms thrown up, land.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Jump(pub GaitParams);
+
+impl Default for Jump {
+    fn default() -> Self {
+        Self(GaitParams::for_kind(GaitKind::Jump))
+    }
+}
+
+impl Gait for Jump {
+    fn rate(&self, _speed: f32) -> f32 {
+        self.0.cadence
+    }
+
+    fn stride(&self) -> f32 {
+        0.0
+    }
+
+    fn pose(&self, phase: f32, _beat: f32) -> Pose {
+        const CROUCH: f32 = 0.2;
+        let p = &self.0;
+        let t = phase.rem_euclid(1.0);
+
+        // Crouch before take off and after landing, ballistic in between
+        let (height, air) = if (CROUCH..=1.0 - CROUCH).contains(&t) {
+            let flight = (t - CROUCH) / (1.0 - 2.0 * CROUCH);
+            (4.0 * flight * (1.0 - flight) * p.bob, (flight * PI).sin())
+        } else {
+            let crouch = ((t.min(1.0 - t) / CROUCH) * PI).sin();
+            (-0.3 * crouch, 0.0)
+        };
+        let arms = -air * p.arm_swing;
+        // Feet leave the ground with the body, tucked up at the top
+        let lift = (height - air * p.foot_lift).max(0.0);
+        let rest = Pose::default().feet;
+        Pose {
+            height,
+            torso: Vector3::new(-height.min(0.0), 0.0, 0.0),
+            arms: [Vector3::new(arms, 0.0, 0.0), Vector3::new(arms, 0.0, 0.0)],
+            feet: rest.map(|f| f + Vector3::new(0.0, 0.0, lift)),
+            ..Pose::default()
+        }
+    }
+}
+
+/// Bouncing on every beat with alternating raised arms, one cycle every two
+/// beats. Follows the beat rather than the phase so it stays in time with
+/// the soundtrack whatever the tempo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DanceOnBeat(pub GaitParams);
+
+impl Default for DanceOnBeat {
+    fn default() -> Self {
+        Self(GaitParams::for_kind(GaitKind::Dance))
+    }
+}
+
+impl Gait for DanceOnBeat {
+    fn rate(&self, _speed: f32) -> f32 {
+        self.0.cadence
+    }
+
+    fn stride(&self) -> f32 {
+        0.0
+    }
+
+    fn pose(&self, _phase: f32, beat: f32) -> Pose {
+        let p = &self.0;
+        let cycle = beat * 0.5;
+        let beat = beat.rem_euclid(1.0);
+        // Drop on the beat, like the kick envelope
+        let bounce = (-beat * 6.0).exp();
+        let side = (cycle * TAU).sin();
+        let (left, right) = (side.max(0.0) * p.arm_swing, (-side).max(0.0) * p.arm_swing);
+        Pose {
+            height: -bounce * p.bob,
+            torso: Vector3::new(0.0, side * 0.15, side * 0.3),
+            head: Vector3::new(bounce * 0.3, 0.0, 0.0),
+            arms: [Vector3::new(0.0, -left, 0.0), Vector3::new(0.0, right, 0.0)],
+            reach: 0.95,
+            // Wide stance, lifting the foot on the side the torso leans away from
+            feet: [
+                Vector3::new(HIP.x + 0.15, 0.0, (-side).max(0.0) * p.foot_lift),
+                Vector3::new(-HIP.x - 0.15, 0.0, side.max(0.0) * p.foot_lift),
+            ],
+        }
+    }
+}
+
+/// Foot targets of a stepping gait, left foot first, and the swing of the
+/// left leg from -1 (forward) to 1 (back).
+///
+/// Each foot is planted for `stance` of the cycle, sliding back exactly as
+/// fast as the root moves forward when the phase advances one cycle per
+/// `stride`, then lifted by up to `lift` and swung forward again.
+fn stepping(phase: f32, stride: f32, stance: f32, lift: f32) -> ([Vector3; 2], f32) {
+    let reach = stance * stride * 0.5;
+    let foot = |side: f32, offset: f32| {
+        let t = (phase + offset).rem_euclid(1.0);
+        let (y, z) = if t < stance {
+            (stride * (t - stance * 0.5), 0.0)
+        } else {
+            let s = (t - stance) / (1.0 - stance);
+            (reach - 2.0 * reach * s * s * (3.0 - 2.0 * s), (s * PI).sin() * lift)
+        };
+        Vector3::new(side * HIP.x, y, z)
+    };
+    let feet = [foot(1.0, 0.0), foot(-1.0, 0.5)];
+    let swing = if reach > 0.0 { feet[0].y / reach } else { 0.0 };
+    (feet, swing)
+}
+
+/// A gait starting at `start`, crossfading from the previous one over
+/// `blend` frames.
+pub struct GaitSegment {
+    pub start: i32,
+    pub blend: i32,
+    pub gait: Box<dyn Gait>,
+}
+
+/// Gaits over the course of the render.
+pub struct GaitTimeline {
+    segments: Vec<GaitSegment>,
+}
+
+impl GaitTimeline {
+    /// The segments are sorted by `start`; the first one also covers the
+    /// frames before its start.
+    pub fn new(mut segments: Vec<GaitSegment>) -> Self {
+        segments.sort_by_key(|s| s.start);
+        Self { segments }
+    }
+
+    /// Poses for frames `0..=frames`, with `beat(frame)` the beats elapsed
+    /// in the soundtrack and `speed(frame)` the root's speed in units per
+    /// second.
+    pub fn poses(&self, frames: i32, fps: u32, beat: impl Fn(i32) -> f32, speed: impl Fn(i32) -> f32) -> Vec<Pose> {
+        let mut phase = 0.0;
+        (0..=frames)
+            .map(|frame| {
+                let weights = self.weights(frame);
+                let rate: f32 = weights.iter().map(|&(i, w)| w * self.segments[i].gait.rate(speed(frame))).sum();
+                if frame > 0 {
+                    phase += rate / fps as f32;
+                }
+                let beat = beat(frame);
+                let pose = |i: usize| self.segments[i].gait.pose(phase, beat);
+                match weights[..] {
+                    [(i, _)] => pose(i),
+                    [(from, _), (to, w)] => pose(from).lerp(&pose(to), w),
+                    _ => Pose::default(),
+                }
+            })
+            .collect()
+    }
+
+    /// How much of the path's speed the root moves with at `frame`: 1 for
+    /// the moving gaits, 0 for those that stay in place, blended across
+    /// crossfades.
+    pub fn travel(&self, frame: i32) -> f32 {
+        self.weights(frame).iter().filter(|&&(i, _)| self.segments[i].gait.stride() > 0.0).map(|&(_, w)| w).sum()
+    }
+
+    /// Distance covered per cycle at `frame`, blended across crossfades: 0
+    /// while only gaits that stay in place play.
+    pub fn stride(&self, frame: i32) -> f32 {
+        self.weights(frame).iter().map(|&(i, w)| w * self.segments[i].gait.stride()).sum()
+    }
+
+    /// The active segments at `frame`, oldest first, with their weights.
+    fn weights(&self, frame: i32) -> Vec<(usize, f32)> {
+        if self.segments.is_empty() {
+            return Vec::new();
+        }
+        let current = self.segments.iter().rposition(|s| s.start <= frame).unwrap_or(0);
+        let segment = &self.segments[current];
+        let t = if segment.blend > 0 { (frame - segment.start) as f32 / segment.blend as f32 } else { 1.0 };
+        if current == 0 || t >= 1.0 {
+            return vec![(current, 1.0)];
+        }
+        let w = t * t * (3.0 - 2.0 * t);
+        vec![(current - 1, 1.0 - w), (current, w)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(gaits: &[(GaitKind, i32, i32)]) -> GaitTimeline {
+        GaitTimeline::new(
+            gaits
+                .iter()
+                .map(|&(kind, start, blend)| GaitSegment { start, blend, gait: GaitParams::for_kind(kind).gait(kind) })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn in_place_gaits_do_not_travel() {
+        for kind in [GaitKind::Idle, GaitKind::Jump, GaitKind::Dance] {
+            assert_eq!(GaitParams::for_kind(kind).gait(kind).stride(), 0.0, "{:?}", kind);
+            let gaits = timeline(&[(kind, 0, 0)]);
+            assert!((0..100).all(|frame| gaits.travel(frame) == 0.0), "{:?}", kind);
+        }
+        for kind in [GaitKind::Walk, GaitKind::Run] {
+            assert!(GaitParams::for_kind(kind).gait(kind).stride() > 0.0, "{:?}", kind);
+        }
+
+        // The root eases to a stop over the crossfade into an idle
+        let gaits = timeline(&[(GaitKind::Walk, 0, 0), (GaitKind::Idle, 30, 10)]);
+        assert_eq!((gaits.travel(29), gaits.travel(40)), (1.0, 0.0));
+        assert!(gaits.travel(35) > 0.0 && gaits.travel(35) < 1.0);
+    }
+
+    #[test]
+    fn crossfade_weights_sum_to_one_and_move_monotonically() {
+        let gaits = timeline(&[(GaitKind::Walk, 0, 0), (GaitKind::Run, 20, 30), (GaitKind::Dance, 80, 15)]);
+        let mut previous = (0, 0.0);
+        for frame in 0..120 {
+            let weights = gaits.weights(frame);
+            let sum: f32 = weights.iter().map(|&(_, w)| w).sum();
+            assert!((sum - 1.0).abs() < 1e-6, "frame {}: {:?}", frame, weights);
+
+            // The incoming gait only gains weight until it takes over
+            let &(current, w) = weights.last().unwrap();
+            if current == previous.0 {
+                assert!(w >= previous.1, "frame {}: {:?}", frame, weights);
+            }
+            previous = (current, w);
+        }
+        assert_eq!(gaits.weights(20), [(0, 1.0), (1, 0.0)]);
+        assert_eq!(gaits.weights(50), [(1, 1.0)]);
+        assert_eq!(gaits.weights(119), [(2, 1.0)]);
+    }
+}
@@ -0,0 +1,250 @@
+//! glTF 2.0 binary (.glb) export of the computed animation.
+//!
+//! Each object becomes a node (parented like in Blender) with a mesh for its
+//! `object_type` and a material from its color and emission. The animation
+//! channels are sampled every frame into linear translation, rotation and
+//! scale tracks. glTF is Y-up, so positions and rotations are converted from
+//! Blender's Z-up on the way out; Blender's importer converts them back.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::anim::ObjAnimData;
+use crate::math::Quat;
+use crate::mesh::Mesh;
+use crate::scene::TransformGraph;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Writes `anim_data` as a .glb file, sampling frames `0..=frames` at `fps`.
+pub fn write_glb(path: impl AsRef<Path>, anim_data: &[ObjAnimData], frames: i32, fps: u32) -> std::io::Result<()> {
+    let (json, bin) = build(anim_data, frames, fps)?;
+    let mut json = serde_json::to_vec(&json).map_err(std::io::Error::other)?;
+    pad(&mut json, b' ');
+    let mut bin = bin;
+    pad(&mut bin, 0);
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(GLB_MAGIC)?;
+    writer.write_all(&2_u32.to_le_bytes())?;
+    writer.write_all(&(length as u32).to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(CHUNK_JSON)?;
+    writer.write_all(&json)?;
+    writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+    writer.write_all(CHUNK_BIN)?;
+    writer.write_all(&bin)?;
+    writer.flush()
+}
+
+/// Builds the glTF JSON document and its binary buffer.
+fn build(anim_data: &[ObjAnimData], frames: i32, fps: u32) -> std::io::Result<(Value, Vec<u8>)> {
+    let graph = TransformGraph::new(anim_data.iter().map(|d| (d.name.as_str(), d.parent.as_deref())))?;
+    let mut buffer = Buffer::default();
+    let mut nodes = Vec::new();
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); anim_data.len()];
+    let mut roots = Vec::new();
+
+    for i in 0..anim_data.len() {
+        match graph.parent(i) {
+            Some(parent) => children[parent].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    let mut uses_emissive_strength = false;
+    for (i, data) in anim_data.iter().enumerate() {
+        let mut mesh = Mesh::primitive(&data.object_type)
+            .ok_or_else(|| invalid(format!("unsupported object_type {} for {}", data.object_type, data.name)))?;
+        // The static scale and pivot are baked into the mesh, as in Blender
+        mesh.scale(data.scale);
+        mesh.translate(data.pivot.map(|v| -v));
+        for v in mesh.positions.iter_mut().chain(mesh.normals.iter_mut()) {
+            *v = y_up(*v);
+        }
+        let (min, max) = mesh.bounds();
+
+        let position =
+            buffer.push_f32(&mesh.positions.concat(), ARRAY_BUFFER, "VEC3", Some((min.to_vec(), max.to_vec())));
+        let normal = buffer.push_f32(&mesh.normals.concat(), ARRAY_BUFFER, "VEC3", None);
+        let indices = buffer.push_u32(&mesh.indices, ELEMENT_ARRAY_BUFFER);
+
+        let [r, g, b, a] = data.color;
+        let mut material = json!({
+            "name": format!("{}.Material", data.name),
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [r, g, b, a],
+                "metallicFactor": 0.0,
+                "roughnessFactor": 0.5,
+            },
+        });
+        if data.emission > 0.0 {
+            material["emissiveFactor"] = json!([r, g, b]);
+            if data.emission != 1.0 {
+                material["extensions"] = json!({
+                    "KHR_materials_emissive_strength": { "emissiveStrength": data.emission }
+                });
+                uses_emissive_strength = true;
+            }
+        }
+        materials.push(material);
+
+        meshes.push(json!({
+            "name": data.name,
+            "primitives": [{
+                "attributes": { "POSITION": position, "NORMAL": normal },
+                "indices": indices,
+                "material": i,
+            }],
+        }));
+
+        let mut node = json!({ "name": data.name, "mesh": i });
+        if !children[i].is_empty() {
+            node["children"] = json!(children[i]);
+        }
+        nodes.push(node);
+    }
+
+    // Animation: every node gets linear TRS tracks over a shared time input
+    let times: Vec<f32> = (0..=frames).map(|f| f as f32 / fps as f32).collect();
+    let last = *times.last().unwrap_or(&0.0);
+    let input = buffer.push_f32(&times, 0, "SCALAR", Some((vec![0.0], vec![last])));
+    let mut samplers = Vec::new();
+    let mut channels = Vec::new();
+
+    for (i, data) in anim_data.iter().enumerate() {
+        let mut translations = Vec::with_capacity(times.len() * 3);
+        let mut rotations = Vec::with_capacity(times.len() * 4);
+        let mut scales = Vec::with_capacity(times.len() * 3);
+        for frame in 0..=frames {
+            let state = data.state_at(frame as f32);
+            let (t, s) = (state.location, state.scale);
+            translations.extend(y_up([t.x, t.y, t.z]));
+            let q = Quat::from_euler(state.rotation, state.rotation_order);
+            rotations.extend([q.x, q.z, -q.y, q.w]);
+            scales.extend([s.x, s.z, s.y]);
+        }
+
+        for (path, values, kind) in
+            [("translation", translations, "VEC3"), ("rotation", rotations, "VEC4"), ("scale", scales, "VEC3")]
+        {
+            let output = buffer.push_f32(&values, 0, kind, None);
+            channels.push(json!({
+                "sampler": samplers.len(),
+                "target": { "node": i, "path": path },
+            }));
+            samplers.push(json!({ "input": input, "output": output, "interpolation": "LINEAR" }));
+        }
+    }
+
+    let mut doc = json!({
+        "asset": { "version": "2.0", "generator": "GhostRender" },
+        "scene": 0,
+        "scenes": [{ "nodes": roots }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "animations": [{ "name": "GhostRender", "channels": channels, "samplers": samplers }],
+        "accessors": buffer.accessors,
+        "bufferViews": buffer.views,
+        "buffers": [{ "byteLength": buffer.data.len() }],
+    });
+    if uses_emissive_strength {
+        doc["extensionsUsed"] = json!(["KHR_materials_emissive_strength"]);
+    }
+
+    Ok((doc, buffer.data))
+}
+
+/// The binary buffer, with one buffer view and accessor per array.
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Buffer {
+    /// Appends `values` and returns the accessor index. `target` 0 means
+    /// the view has no GPU buffer target (e.g. animation data).
+    fn push_f32(&mut self, values: &[f32], target: u32, kind: &str, bounds: Option<(Vec<f32>, Vec<f32>)>) -> usize {
+        let components = components(kind);
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut accessor = json!({
+            "bufferView": self.push_view(&bytes, target),
+            "componentType": FLOAT,
+            "count": values.len() / components,
+            "type": kind,
+        });
+        if let Some((min, max)) = bounds {
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_u32(&mut self, values: &[u32], target: u32) -> usize {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let view = self.push_view(&bytes, target);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": values.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn push_view(&mut self, bytes: &[u8], target: u32) -> usize {
+        pad(&mut self.data, 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if target != 0 {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+}
+
+fn components(kind: &str) -> usize {
+    match kind {
+        "VEC3" => 3,
+        "VEC4" => 4,
+        _ => 1,
+    }
+}
+
+/// Converts a Blender (Z-up) vector to glTF (Y-up).
+fn y_up(v: [f32; 3]) -> [f32; 3] {
+    [v[0], v[2], -v[1]]
+}
+
+/// Pads `data` to a multiple of four bytes, as GLB chunks and buffer views require.
+fn pad(data: &mut Vec<u8>, byte: u8) {
+    while !data.len().is_multiple_of(4) {
+        data.push(byte);
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
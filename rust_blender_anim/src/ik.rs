@@ -0,0 +1,147 @@
+//! Analytic two-bone inverse kinematics for the character's limbs.
+//!
+//! Bones hang along -Z in their rest pose and bend about their local X axis,
+//! like the thigh and shin (or upper arm and forearm) built by
+//! [`crate::scene::character`]. The chain is solved with the law of cosines,
+//! so it's exact and needs no iteration.
+
+use std::f32::consts::PI;
+
+use crate::math::{Mat4, Quat};
+use crate::scene::Vector3;
+
+/// Keeps the chain just short of straight, where the bend direction is lost.
+const MIN_BEND: f32 = 1e-3;
+
+/// A chain of two bones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TwoBone {
+    pub upper: f32,
+    pub lower: f32,
+}
+
+/// The solved chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TwoBonePose {
+    /// Rotation pointing the rest bone (-Z) straight at the target, with X
+    /// along the hinge.
+    pub aim: Mat4,
+    /// Rotation of the upper bone about the aim's X axis.
+    pub lift: f32,
+    /// Rotation of the lower bone about its local X axis, relative to the upper bone.
+    pub bend: f32,
+    /// Whether the end of the chain reaches the target. Targets out of reach
+    /// get the chain pointing at them, fully stretched.
+    pub reached: bool,
+}
+
+impl TwoBone {
+    pub fn new(upper: f32, lower: f32) -> Self {
+        Self { upper, lower }
+    }
+
+    /// Solves for the end of the chain to reach `target`, relative to the
+    /// root joint. `hinge` is the approximate axis the middle joint bends
+    /// about, and the joint moves towards `pole`.
+    pub fn solve(&self, target: Vector3, hinge: Vector3, pole: Vector3) -> TwoBonePose {
+        let (a, b) = (self.upper, self.lower);
+        let distance = target.length();
+        let reached = distance <= a + b && distance >= (a - b).abs();
+        let d = distance.clamp((a - b).abs() + MIN_BEND, a + b - MIN_BEND);
+
+        // Frame aiming the rest bone (-Z) at the target, X as close to the hinge as possible
+        let dir = if distance > 0.0 { target * (1.0 / distance) } else { Vector3::new(0.0, 0.0, -1.0) };
+        let z = dir * -1.0;
+        let mut x = hinge - z * hinge.dot(z);
+        if x.length() < 1e-4 {
+            // The hinge is along the target (or zero): bend about whichever
+            // axis is furthest from it instead
+            let fallback = if z.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+            x = fallback - z * fallback.dot(z);
+        }
+        let x = x.normalized();
+        let y = z.cross(x);
+
+        // Interior angles at the root and middle joints
+        let root = ((a * a + d * d - b * b) / (2.0 * a * d)).clamp(-1.0, 1.0).acos();
+        let middle = ((a * a + b * b - d * d) / (2.0 * a * b)).clamp(-1.0, 1.0).acos();
+        // Rotating about +X swings the bone's end towards +Y
+        let side = if pole.dot(y) < 0.0 { -1.0 } else { 1.0 };
+
+        TwoBonePose { aim: Mat4::from_axes(x, y, z), lift: side * root, bend: -side * (PI - middle), reached }
+    }
+}
+
+impl TwoBonePose {
+    /// Rotation of the upper bone, in the space the target was given in.
+    pub fn upper(&self) -> Mat4 {
+        self.aim * Mat4::from_quat(Quat::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), self.lift))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HINGE: Vector3 = Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+    const POLE: Vector3 = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+
+    /// Where the end of the solved chain lands, relative to the root.
+    fn end(chain: &TwoBone, pose: &TwoBonePose) -> Vector3 {
+        let down = |length: f32| Vector3::new(0.0, 0.0, -length);
+        let bend = Mat4::from_quat(Quat::from_axis_angle(HINGE, pose.bend));
+        pose.upper().transform_vector(down(chain.upper) + bend.transform_vector(down(chain.lower)))
+    }
+
+    fn assert_finite(pose: &TwoBonePose) {
+        assert!(pose.lift.is_finite() && pose.bend.is_finite(), "{:?}", pose);
+        let axis = pose.aim.transform_vector(HINGE);
+        assert!(axis.x.is_finite() && axis.y.is_finite() && axis.z.is_finite(), "{:?}", pose);
+        assert!((axis.length() - 1.0).abs() < 1e-4, "hinge axis {:?}", axis);
+    }
+
+    #[test]
+    fn reachable_targets_are_reached() {
+        let chain = TwoBone::new(0.5, 0.4);
+        for target in [Vector3::new(0.0, 0.2, -0.6), Vector3::new(0.3, -0.1, -0.5), Vector3::new(0.0, 0.0, -0.2)] {
+            let pose = chain.solve(target, HINGE, POLE);
+            assert!(pose.reached);
+            assert_finite(&pose);
+            let error = (end(&chain, &pose) - target).length();
+            assert!(error < 1e-3, "missed {:?} by {}", target, error);
+        }
+    }
+
+    #[test]
+    fn the_middle_joint_bends_towards_the_pole() {
+        let chain = TwoBone::new(0.5, 0.5);
+        let pose = chain.solve(Vector3::new(0.0, 0.0, -0.6), HINGE, POLE);
+        let knee = pose.upper().transform_vector(Vector3::new(0.0, 0.0, -chain.upper));
+        assert!(knee.y > 0.1, "knee at {:?}", knee);
+    }
+
+    #[test]
+    fn unreachable_targets_straighten_the_chain() {
+        let chain = TwoBone::new(0.5, 0.4);
+        let target = Vector3::new(0.0, 1.0, -2.0);
+        let pose = chain.solve(target, HINGE, POLE);
+        assert!(!pose.reached);
+        assert_finite(&pose);
+        let end = end(&chain, &pose);
+        assert!((end.length() - 0.9).abs() < 1e-3, "stretched to {}", end.length());
+        assert!(end.normalized().dot(target.normalized()) > 0.9999, "points at {:?}", end);
+    }
+
+    #[test]
+    fn degenerate_hinges_fall_back_to_another_axis() {
+        let chain = TwoBone::new(0.5, 0.4);
+        let target = Vector3::new(0.6, 0.0, 0.0);
+        for hinge in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(-2.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)] {
+            let pose = chain.solve(target, hinge, POLE);
+            assert_finite(&pose);
+            assert!((end(&chain, &pose) - target).length() < 1e-3);
+        }
+        // A target at the root itself
+        assert_finite(&chain.solve(Vector3::new(0.0, 0.0, 0.0), HINGE, POLE));
+    }
+}
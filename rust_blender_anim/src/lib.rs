@@ -0,0 +1,28 @@
+//! GhostRender: procedural animation computed in Rust, rendered by Blender.
+//!
+//! The [`Pipeline`] type exposes each stage of the render (animation
+//! sampling, script generation, scene setup, parallel rendering and
+//! concatenation) so it can be embedded in other build tools. The `scene`
+//! and `audio` modules can also be used on their own.
+
+pub mod anim;
+pub mod audio;
+pub mod bvh;
+pub mod config;
+pub mod curve;
+pub mod gait;
+pub mod gltf;
+pub mod ik;
+pub mod math;
+pub mod mesh;
+pub mod mocap;
+pub mod path;
+pub mod pipeline;
+pub mod preview;
+pub mod scene;
+pub mod script;
+pub mod tempo;
+
+pub use anim::ObjAnimData;
+pub use config::Config;
+pub use pipeline::Pipeline;
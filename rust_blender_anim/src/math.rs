@@ -0,0 +1,289 @@
+//! Small linear algebra helpers shared by the exporters.
+
+use std::f32::consts::{PI, TAU};
+use std::ops::Mul;
+
+use serde::Serialize;
+
+use crate::scene::Vector3;
+
+/// Euler rotation order, named like Blender's rotation modes: `Xyz` applies
+/// the X rotation first and Z last.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EulerOrder {
+    #[default]
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    Zxy,
+    Zyx,
+}
+
+impl EulerOrder {
+    /// Axis indices in the order the rotations are applied.
+    pub fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::Xyz => [0, 1, 2],
+            EulerOrder::Xzy => [0, 2, 1],
+            EulerOrder::Yxz => [1, 0, 2],
+            EulerOrder::Yzx => [1, 2, 0],
+            EulerOrder::Zxy => [2, 0, 1],
+            EulerOrder::Zyx => [2, 1, 0],
+        }
+    }
+}
+
+/// The Euler angles equivalent to `e` closest to `previous`, in any order,
+/// so sampled curves don't jump by whole turns or flip between the two
+/// decompositions of a rotation.
+pub fn nearest_euler(e: Vector3, previous: Vector3) -> Vector3 {
+    let wrap = |angle: f32, near: f32| angle - ((angle - near) / TAU).round() * TAU;
+    [e, Vector3::new(e.x + PI, PI - e.y, e.z + PI)]
+        .into_iter()
+        .map(|c| Vector3::new(wrap(c.x, previous.x), wrap(c.y, previous.y), wrap(c.z, previous.z)))
+        .min_by(|a, b| (*a - previous).length().total_cmp(&(*b - previous).length()))
+        .unwrap()
+}
+
+/// A rotation quaternion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat { x: 0.0, y: 0.0, z: 0.0, w: 1.0 };
+
+    pub fn from_axis_angle(axis: Vector3, angle: f32) -> Self {
+        let (s, c) = (angle * 0.5).sin_cos();
+        Self { x: axis.x * s, y: axis.y * s, z: axis.z * s, w: c }
+    }
+
+    /// Rotation for Blender's default `XYZ` Euler mode (X applied first).
+    pub fn from_euler_xyz(rotation: Vector3) -> Self {
+        Self::from_euler(rotation, EulerOrder::Xyz)
+    }
+
+    pub fn from_euler(rotation: Vector3, order: EulerOrder) -> Self {
+        let angles = [rotation.x, rotation.y, rotation.z];
+        order.axes().iter().fold(Self::IDENTITY, |q, &axis| {
+            let mut v = [0.0; 3];
+            v[axis] = 1.0;
+            Self::from_axis_angle(Vector3::new(v[0], v[1], v[2]), angles[axis]) * q
+        })
+    }
+
+    pub fn normalized(self) -> Self {
+        let len = (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w).sqrt();
+        if len > 0.0 {
+            Self { x: self.x / len, y: self.y / len, z: self.z / len, w: self.w / len }
+        } else {
+            Self::IDENTITY
+        }
+    }
+
+    /// Spherical interpolation from `self` (at 0) to `other` (at 1), along
+    /// the shorter arc.
+    pub fn slerp(self, other: Quat, t: f32) -> Self {
+        let mut dot = self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w;
+        let other = if dot < 0.0 {
+            dot = -dot;
+            Quat { x: -other.x, y: -other.y, z: -other.z, w: -other.w }
+        } else {
+            other
+        };
+        let (a, b) = if dot > 0.9995 {
+            // Nearly parallel: linear is accurate and avoids dividing by ~0
+            (1.0 - t, t)
+        } else {
+            let theta = dot.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quat {
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+            w: self.w * a + other.w * b,
+        }
+        .normalized()
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        [self.x, self.y, self.z, self.w]
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, r: Quat) -> Quat {
+        Quat {
+            w: self.w * r.w - self.x * r.x - self.y * r.y - self.z * r.z,
+            x: self.w * r.x + self.x * r.w + self.y * r.z - self.z * r.y,
+            y: self.w * r.y - self.x * r.z + self.y * r.w + self.z * r.x,
+            z: self.w * r.z + self.x * r.y - self.y * r.x + self.z * r.w,
+        }
+    }
+}
+
+/// A column-major 4x4 transform matrix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub cols: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 =
+        Mat4 { cols: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] };
+
+    pub fn from_quat(q: Quat) -> Self {
+        let (x, y, z, w) = (q.x, q.y, q.z, q.w);
+        Mat4 {
+            cols: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + w * z), 2.0 * (x * z - w * y), 0.0],
+                [2.0 * (x * y - w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + w * x), 0.0],
+                [2.0 * (x * z + w * y), 2.0 * (y * z - w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Translation * rotation * scale, the order Blender composes object transforms in.
+    pub fn from_trs(translation: Vector3, rotation: Quat, scale: Vector3) -> Self {
+        let mut m = Self::from_quat(rotation);
+        for (col, s) in m.cols.iter_mut().zip([scale.x, scale.y, scale.z]) {
+            for v in col.iter_mut().take(3) {
+                *v *= s;
+            }
+        }
+        m.cols[3] = [translation.x, translation.y, translation.z, 1.0];
+        m
+    }
+
+    /// View matrix for a camera at `eye` looking at `target`, with `up` kept
+    /// vertical on screen. The camera looks down its local -Z axis.
+    pub fn look_at(eye: Vector3, target: Vector3, up: Vector3) -> Self {
+        let f = (target - eye).normalized();
+        let s = f.cross(up).normalized();
+        let u = s.cross(f);
+        Mat4 {
+            cols: [
+                [s.x, u.x, -f.x, 0.0],
+                [s.y, u.y, -f.y, 0.0],
+                [s.z, u.z, -f.z, 0.0],
+                [-s.dot(eye), -u.dot(eye), f.dot(eye), 1.0],
+            ],
+        }
+    }
+
+    /// Inverse of a rotation and translation (no scale).
+    pub fn inverse_rigid(self) -> Self {
+        let c = &self.cols;
+        let mut m = Mat4::IDENTITY;
+        for (i, col) in m.cols.iter_mut().take(3).enumerate() {
+            *col = [c[0][i], c[1][i], c[2][i], 0.0];
+        }
+        let t = m.transform_vector(self.translation());
+        m.cols[3] = [-t.x, -t.y, -t.z, 1.0];
+        m
+    }
+
+    /// A rotation from its X, Y and Z axes.
+    pub fn from_axes(x: Vector3, y: Vector3, z: Vector3) -> Self {
+        Mat4 { cols: [[x.x, x.y, x.z, 0.0], [y.x, y.y, y.z, 0.0], [z.x, z.y, z.z, 0.0], [0.0, 0.0, 0.0, 1.0]] }
+    }
+
+    /// Euler angles of the rotation part, the inverse of [`Quat::from_euler`].
+    pub fn to_euler(self, order: EulerOrder) -> Vector3 {
+        let [i, j, k] = order.axes();
+        // Element at `row`, `col`
+        let m = |row: usize, col: usize| self.cols[col][row];
+        // Cyclic orders (XYZ, YZX, ZXY) flip the signs of the others
+        let sign = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+        let mut angles = [0.0; 3];
+        angles[j] = (-sign * m(k, i)).clamp(-1.0, 1.0).asin();
+        if m(k, i).abs() < 0.999_999 {
+            angles[i] = (sign * m(k, j)).atan2(m(k, k));
+            angles[k] = (sign * m(j, i)).atan2(m(i, i));
+        } else {
+            // Gimbal lock: only the sum or difference of the outer angles is defined
+            angles[i] = (-sign * m(j, k)).atan2(m(j, j));
+        }
+        Vector3::new(angles[0], angles[1], angles[2])
+    }
+
+    pub fn translation(self) -> Vector3 {
+        Vector3::new(self.cols[3][0], self.cols[3][1], self.cols[3][2])
+    }
+
+    pub fn transform_point(self, p: Vector3) -> Vector3 {
+        self.transform_vector(p) + self.translation()
+    }
+
+    pub fn transform_vector(self, v: Vector3) -> Vector3 {
+        let c = &self.cols;
+        Vector3::new(
+            c[0][0] * v.x + c[1][0] * v.y + c[2][0] * v.z,
+            c[0][1] * v.x + c[1][1] * v.y + c[2][1] * v.z,
+            c[0][2] * v.x + c[1][2] * v.y + c[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, r: Mat4) -> Mat4 {
+        let mut cols = [[0.0; 4]; 4];
+        for (c, col) in cols.iter_mut().enumerate() {
+            for (row, v) in col.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.cols[k][row] * r.cols[c][k]).sum();
+            }
+        }
+        Mat4 { cols }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [EulerOrder; 6] =
+        [EulerOrder::Xyz, EulerOrder::Xzy, EulerOrder::Yxz, EulerOrder::Yzx, EulerOrder::Zxy, EulerOrder::Zyx];
+
+    #[test]
+    fn to_euler_inverts_from_euler_in_every_order() {
+        let rotation = Vector3::new(0.3, -0.7, 1.2);
+        for order in ORDERS {
+            let angles = Mat4::from_quat(Quat::from_euler(rotation, order)).to_euler(order);
+            assert!((angles - rotation).length() < 1e-4, "{:?}: {:?}", order, angles);
+        }
+    }
+
+    #[test]
+    fn nearest_euler_unwraps_across_pi() {
+        // Just past +pi comes back as just past -pi
+        let e = nearest_euler(Vector3::new(0.0, 0.0, -PI + 0.1), Vector3::new(0.0, 0.0, PI - 0.1));
+        assert!((e.z - (PI + 0.1)).abs() < 1e-5, "{:?}", e);
+        // Whole turns are removed on every axis
+        let e = nearest_euler(Vector3::new(0.2, 0.1, 0.3), Vector3::new(TAU, -TAU, 2.0 * TAU));
+        assert!((e - Vector3::new(TAU + 0.2, 0.1 - TAU, 2.0 * TAU + 0.3)).length() < 1e-4, "{:?}", e);
+    }
+
+    #[test]
+    fn nearest_euler_prefers_the_closer_decomposition() {
+        // A Y past 90 degrees comes back from to_euler as the other decomposition
+        let previous = Vector3::new(0.0, 1.5, 0.0);
+        let rotation = Vector3::new(0.0, 1.7, 0.0);
+        let decomposed = Mat4::from_quat(Quat::from_euler_xyz(rotation)).to_euler(EulerOrder::Xyz);
+        assert!((decomposed - rotation).length() > 1.0);
+        let e = nearest_euler(decomposed, previous);
+        assert!((e - rotation).length() < 1e-4, "{:?}", e);
+    }
+}
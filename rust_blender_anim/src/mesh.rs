@@ -0,0 +1,169 @@
+//! Triangle meshes for the object primitives, matching the unit-sized
+//! Blender primitives created by the setup script (Z up).
+
+use std::f32::consts::PI;
+
+const SEGMENTS: usize = 16;
+const RINGS: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Counter-clockwise triangles.
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// The mesh for an object type (`"CUBE"`, `"CYLINDER"` or `"SPHERE"`).
+    pub fn primitive(object_type: &str) -> Option<Mesh> {
+        match object_type {
+            "CUBE" => Some(Self::cube()),
+            "CYLINDER" => Some(Self::cylinder()),
+            "SPHERE" => Some(Self::sphere()),
+            _ => None,
+        }
+    }
+
+    /// Axis-aligned cube with edge length 1, centered on the origin.
+    pub fn cube() -> Mesh {
+        let mut mesh = Mesh::default();
+        for axis in 0..3 {
+            for sign in [1.0f32, -1.0] {
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                // Two tangent axes, ordered so the face winds counter-clockwise
+                let (u, v) =
+                    if sign > 0.0 { ((axis + 1) % 3, (axis + 2) % 3) } else { ((axis + 2) % 3, (axis + 1) % 3) };
+                let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+                let quad: Vec<[f32; 3]> = corners
+                    .iter()
+                    .map(|&(a, b)| {
+                        let mut p = [0.0; 3];
+                        p[axis] = 0.5 * sign;
+                        p[u] = a;
+                        p[v] = b;
+                        p
+                    })
+                    .collect();
+                mesh.push_quad([quad[0], quad[1], quad[2], quad[3]], normal);
+            }
+        }
+        mesh
+    }
+
+    /// Square with edge length 1 in the XY plane, facing +Z.
+    pub fn plane() -> Mesh {
+        let mut mesh = Mesh::default();
+        mesh.push_quad([[-0.5, -0.5, 0.0], [0.5, -0.5, 0.0], [0.5, 0.5, 0.0], [-0.5, 0.5, 0.0]], [0.0, 0.0, 1.0]);
+        mesh
+    }
+
+    /// Cylinder along Z with radius 0.5 and height 1.
+    pub fn cylinder() -> Mesh {
+        let mut mesh = Mesh::default();
+        let ring = |i: usize| {
+            let a = 2.0 * PI * i as f32 / SEGMENTS as f32;
+            (0.5 * a.cos(), 0.5 * a.sin(), a)
+        };
+
+        for i in 0..SEGMENTS {
+            let (x0, y0, a0) = ring(i);
+            let (x1, y1, a1) = ring(i + 1);
+
+            // Side, smooth shaded
+            let base = mesh.positions.len() as u32;
+            mesh.positions.extend([[x0, y0, -0.5], [x1, y1, -0.5], [x1, y1, 0.5], [x0, y0, 0.5]]);
+            mesh.normals.extend([
+                [a0.cos(), a0.sin(), 0.0],
+                [a1.cos(), a1.sin(), 0.0],
+                [a1.cos(), a1.sin(), 0.0],
+                [a0.cos(), a0.sin(), 0.0],
+            ]);
+            mesh.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+
+            // Caps
+            mesh.push_triangle([[0.0, 0.0, 0.5], [x0, y0, 0.5], [x1, y1, 0.5]], [0.0, 0.0, 1.0]);
+            mesh.push_triangle([[0.0, 0.0, -0.5], [x1, y1, -0.5], [x0, y0, -0.5]], [0.0, 0.0, -1.0]);
+        }
+        mesh
+    }
+
+    /// UV sphere with radius 0.5.
+    pub fn sphere() -> Mesh {
+        let mut mesh = Mesh::default();
+        for ring in 0..=RINGS {
+            let theta = PI * ring as f32 / RINGS as f32;
+            for segment in 0..=SEGMENTS {
+                let phi = 2.0 * PI * segment as f32 / SEGMENTS as f32;
+                let n = [theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()];
+                mesh.positions.push([n[0] * 0.5, n[1] * 0.5, n[2] * 0.5]);
+                mesh.normals.push(n);
+            }
+        }
+
+        let stride = SEGMENTS as u32 + 1;
+        for ring in 0..RINGS as u32 {
+            for segment in 0..SEGMENTS as u32 {
+                let a = ring * stride + segment;
+                let b = a + stride;
+                if ring != 0 {
+                    mesh.indices.extend([a, b, a + 1]);
+                }
+                if ring != RINGS as u32 - 1 {
+                    mesh.indices.extend([a + 1, b, b + 1]);
+                }
+            }
+        }
+        mesh
+    }
+
+    /// Scales the vertices, keeping the normals perpendicular to the surface.
+    pub fn scale(&mut self, s: [f32; 3]) {
+        for p in &mut self.positions {
+            *p = [p[0] * s[0], p[1] * s[1], p[2] * s[2]];
+        }
+        for n in &mut self.normals {
+            // Normals transform with the inverse scale
+            let scaled = [n[0] * s[1] * s[2], n[1] * s[0] * s[2], n[2] * s[0] * s[1]];
+            let len = (scaled[0] * scaled[0] + scaled[1] * scaled[1] + scaled[2] * scaled[2]).sqrt();
+            if len > 0.0 {
+                *n = [scaled[0] / len, scaled[1] / len, scaled[2] / len];
+            }
+        }
+    }
+
+    /// Moves the vertices by `offset`.
+    pub fn translate(&mut self, offset: [f32; 3]) {
+        for p in &mut self.positions {
+            *p = [p[0] + offset[0], p[1] + offset[1], p[2] + offset[2]];
+        }
+    }
+
+    /// Axis-aligned bounds as `(min, max)`.
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for p in &self.positions {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        (min, max)
+    }
+
+    fn push_quad(&mut self, corners: [[f32; 3]; 4], normal: [f32; 3]) {
+        let base = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal; 4]);
+        self.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    fn push_triangle(&mut self, corners: [[f32; 3]; 3], normal: [f32; 3]) {
+        let base = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal; 3]);
+        self.indices.extend([base, base + 1, base + 2]);
+    }
+}
@@ -0,0 +1,195 @@
+//! Motion capture clips as an animation source.
+//!
+//! A BVH clip is resampled to the project's frame rate and every joint
+//! becomes a glowing box spanning from the joint to its children in the
+//! rest pose, so mocap renders in the same style as the procedural
+//! character. The objects feed [`crate::Pipeline::sample_with`] like
+//! [`crate::scene::character`] does.
+
+use std::path::Path;
+
+use crate::bvh::Bvh;
+use crate::math::{nearest_euler, EulerOrder, Mat4};
+use crate::scene::{kick_envelope, Color, Music, Object, Vector3};
+
+/// Size of the boxes across the bone, in scene units.
+const THICKNESS: f32 = 0.12;
+
+/// A joint's static look.
+#[derive(Clone, Debug)]
+struct Part {
+    name: String,
+    parent: Option<String>,
+    scale: Vector3,
+    pivot: Vector3,
+    head: bool,
+}
+
+/// A BVH clip resampled to whole project frames.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    parts: Vec<Part>,
+    /// Location and `XYZ` Euler rotation of every joint, per frame.
+    poses: Vec<Vec<(Vector3, Vector3)>>,
+}
+
+impl Clip {
+    /// Reads the BVH file at `path`, see [`Clip::new`].
+    pub fn load(path: impl AsRef<Path>, scale: f32, frames: i32, fps: u32) -> std::io::Result<Self> {
+        let bvh = Bvh::parse(&std::fs::read_to_string(path)?)?;
+        Ok(Self::new(&bvh, scale, frames, fps))
+    }
+
+    /// Resamples `bvh` to frames `0..=frames` at `fps`, scaling its units by
+    /// `scale`. The last frame of the clip is held once it runs out.
+    pub fn new(bvh: &Bvh, scale: f32, frames: i32, fps: u32) -> Self {
+        let parts = bvh
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                // Bounds of the joint and the ends of its bones
+                let mut ends: Vec<Vector3> = bvh
+                    .joints
+                    .iter()
+                    .filter(|j| j.parent == Some(i))
+                    .map(|j| j.offset * scale)
+                    .chain(joint.end_site.map(|e| e * scale))
+                    .collect();
+                ends.push(Vector3::default());
+                let min = ends.iter().fold(ends[0], |m, e| Vector3::new(m.x.min(e.x), m.y.min(e.y), m.z.min(e.z)));
+                let max = ends.iter().fold(ends[0], |m, e| Vector3::new(m.x.max(e.x), m.y.max(e.y), m.z.max(e.z)));
+                let thickness = Vector3::new(THICKNESS, THICKNESS, THICKNESS);
+                Part {
+                    name: joint.name.clone(),
+                    parent: joint.parent.map(|p| bvh.joints[p].name.clone()),
+                    scale: max - min + thickness,
+                    pivot: (min + max) * -0.5,
+                    head: joint.name.to_lowercase().contains("head"),
+                }
+            })
+            .collect();
+
+        let last = bvh.frames.len().saturating_sub(1);
+        let mut previous: Vec<Option<Vector3>> = vec![None; bvh.joints.len()];
+        let poses = (0..=frames.max(0))
+            .map(|frame| {
+                let source = if bvh.frame_time > 0.0 { frame as f32 / fps as f32 / bvh.frame_time } else { 0.0 };
+                let i = (source.floor() as usize).min(last);
+                let next = (i + 1).min(last);
+                let t = if next > i { source - i as f32 } else { 0.0 };
+                (0..bvh.joints.len())
+                    .map(|joint| {
+                        let (a, qa) = bvh.local_pose(i, joint);
+                        let (b, qb) = bvh.local_pose(next, joint);
+                        let location = (a + (b - a) * t) * scale;
+                        let rotation = Mat4::from_quat(qa.slerp(qb, t)).to_euler(EulerOrder::Xyz);
+                        let rotation = match previous[joint] {
+                            Some(prev) => nearest_euler(rotation, prev),
+                            None => rotation,
+                        };
+                        previous[joint] = Some(rotation);
+                        (location, rotation)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { parts, poses }
+    }
+
+    /// The clip's objects at `frame`, clamped to the resampled range, with
+    /// the limbs pulsing on the beat and the root glowing with the bass of
+    /// `music`.
+    pub fn objects(&self, frame: i32, music: Music) -> Vec<Object> {
+        let pose = &self.poses[(frame.max(0) as usize).min(self.poses.len() - 1)];
+        let kick_env = kick_envelope(music.beat);
+        self.parts
+            .iter()
+            .zip(pose)
+            .map(|(part, &(location, rotation))| {
+                let (color, emission) = if part.head {
+                    (Color::new(1.0, 0.8, 0.6, 1.0), 0.0)
+                } else if part.parent.is_none() {
+                    (Color::new(0.0, 0.5, 1.0, 1.0), 2.0 + 4.0 * music.bass)
+                } else {
+                    (Color::new(0.0, 0.5, 1.0, 1.0), 2.0 + 4.0 * kick_env)
+                };
+                Object {
+                    name: part.name.clone(),
+                    object_type: "CUBE".to_string(),
+                    location,
+                    rotation,
+                    rotation_order: EulerOrder::Xyz,
+                    scale: part.scale,
+                    pivot: part.pivot,
+                    color,
+                    emission,
+                    visible: true,
+                    parent: part.parent.clone(),
+                    keyframes: vec![],
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one second clip at 30 fps: the root slides along X by one unit and
+    /// turns by `turn` degrees per frame, and a child hangs below it.
+    fn clip(turn: i32) -> Bvh {
+        let mut text = String::from(
+            "HIERARCHY\nROOT Hips\n{\n\tOFFSET 0 0 0\n\tCHANNELS 4 Xposition Yposition Zposition Yrotation\n\
+             \tJOINT Leg\n\t{\n\t\tOFFSET 0 -1 0\n\t\tCHANNELS 1 Xrotation\n\
+             \t\tEnd Site\n\t\t{\n\t\t\tOFFSET 0 -1 0\n\t\t}\n\t}\n}\n\
+             MOTION\nFrames: 31\nFrame Time: 0.0333333\n",
+        );
+        for frame in 0..=30 {
+            text.push_str(&format!("{} 2 0 {} 10\n", frame, frame * turn));
+        }
+        Bvh::parse(&text).unwrap()
+    }
+
+    #[test]
+    fn clips_are_resampled_to_the_project_fps() {
+        // 30 fps to 24 fps: the second of motion spans frames 0..=24
+        let clip = Clip::new(&clip(3), 1.0, 24, 24);
+        assert_eq!(clip.poses.len(), 25);
+
+        // Frame 5 is 6.25 source frames in: interpolated between 6 and 7
+        let hips = &clip.objects(5, Music::default())[0];
+        assert!((hips.location - Vector3::new(6.25, 0.0, 2.0)).length() < 1e-3, "{:?}", hips.location);
+        let turn = (6.25f32 * 3.0).to_radians();
+        assert!((hips.rotation - Vector3::new(0.0, 0.0, turn)).length() < 1e-4, "{:?}", hips.rotation);
+        // The child joint keeps its constant rotation
+        let leg = &clip.objects(5, Music::default())[1];
+        assert!((leg.rotation - Vector3::new(10f32.to_radians(), 0.0, 0.0)).length() < 1e-4, "{:?}", leg.rotation);
+        assert_eq!(leg.parent.as_deref(), Some("Hips"));
+
+        let end = &clip.objects(24, Music::default())[0];
+        assert!((end.location.x - 30.0).abs() < 1e-3 && (end.rotation.z - 90f32.to_radians()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_last_frame_is_held() {
+        let clip = Clip::new(&clip(3), 1.0, 48, 24);
+        assert_eq!(clip.poses.len(), 49);
+        let last = &clip.objects(24, Music::default())[0];
+        for frame in [25, 36, 48, 100] {
+            let held = &clip.objects(frame, Music::default())[0];
+            assert_eq!((held.location, held.rotation), (last.location, last.rotation), "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn rotations_stay_continuous_past_half_a_turn() {
+        // 300 degrees over the clip, passing 180 degrees around frame 14
+        let clip = Clip::new(&clip(10), 1.0, 24, 24);
+        let turns: Vec<f32> = (0..=24).map(|frame| clip.objects(frame, Music::default())[0].rotation.z).collect();
+        assert!(turns.windows(2).all(|w| w[1] > w[0] && w[1] - w[0] < 0.3), "{:?}", turns);
+        assert!((turns[24] - 300f32.to_radians()).abs() < 1e-3, "{:?}", turns);
+    }
+}
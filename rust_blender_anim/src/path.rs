@@ -0,0 +1,296 @@
+//! Root motion: the path the character walks along and how fast.
+//!
+//! Paths are flattened to a dense polyline with cumulative arc lengths, so
+//! positions are looked up by distance travelled rather than by curve
+//! parameter. That keeps the speed constant along curved sections.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::scene::Vector3;
+
+/// Samples per curve segment when flattening Bezier and Catmull-Rom paths.
+const SEGMENT_SAMPLES: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathKind {
+    /// Straight lines through the points.
+    #[default]
+    Polyline,
+    /// Cubic Bezier segments: `p0, c0, c1, p1, c2, c3, p2, ...`.
+    Bezier,
+    /// A smooth curve through all the points.
+    CatmullRom,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    TooFewPoints {
+        kind: PathKind,
+        count: usize,
+    },
+    /// Bezier paths need `3n + 1` points.
+    BezierPointCount(usize),
+    ZeroLength,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::TooFewPoints { kind, count } => {
+                write!(f, "a {:?} path needs at least 2 points (got {})", kind, count)
+            }
+            PathError::BezierPointCount(count) => {
+                write!(f, "a Bezier path needs 3n + 1 points (got {})", count)
+            }
+            PathError::ZeroLength => write!(f, "the path has zero length"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// A path parameterized by arc length.
+#[derive(Clone, Debug)]
+pub struct Path {
+    points: Vec<Vector3>,
+    /// Distance from the start to each point.
+    distances: Vec<f32>,
+}
+
+impl Path {
+    pub fn new(kind: PathKind, control: &[Vector3]) -> Result<Self, PathError> {
+        if control.len() < 2 {
+            return Err(PathError::TooFewPoints { kind, count: control.len() });
+        }
+        let points = match kind {
+            PathKind::Polyline => control.to_vec(),
+            PathKind::Bezier => {
+                if control.len() % 3 != 1 {
+                    return Err(PathError::BezierPointCount(control.len()));
+                }
+                let mut points = vec![control[0]];
+                for segment in control.windows(4).step_by(3) {
+                    points.extend((1..=SEGMENT_SAMPLES).map(|i| {
+                        bezier(segment[0], segment[1], segment[2], segment[3], i as f32 / SEGMENT_SAMPLES as f32)
+                    }));
+                }
+                points
+            }
+            PathKind::CatmullRom => {
+                let n = control.len();
+                // Mirror the end points so the curve starts and ends on them
+                let at = |i: isize| match i {
+                    -1 => control[0] * 2.0 - control[1],
+                    i if i as usize >= n => control[n - 1] * 2.0 - control[n - 2],
+                    i => control[i as usize],
+                };
+                let mut points = vec![control[0]];
+                for i in 0..n as isize - 1 {
+                    let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+                    points.extend(
+                        (1..=SEGMENT_SAMPLES).map(|s| catmull_rom(p0, p1, p2, p3, s as f32 / SEGMENT_SAMPLES as f32)),
+                    );
+                }
+                points
+            }
+        };
+
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        distances.push(0.0);
+        for pair in points.windows(2) {
+            total += (pair[1] - pair[0]).length();
+            distances.push(total);
+        }
+        if total <= 0.0 {
+            return Err(PathError::ZeroLength);
+        }
+
+        Ok(Self { points, distances })
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.distances.last().unwrap()
+    }
+
+    /// The point `distance` along the path, clamped to its ends.
+    pub fn point_at(&self, distance: f32) -> Vector3 {
+        let (i, t) = self.locate(distance);
+        self.points[i] + (self.points[i + 1] - self.points[i]) * t
+    }
+
+    /// Unit direction of travel at `distance`.
+    pub fn tangent_at(&self, distance: f32) -> Vector3 {
+        let (i, _) = self.locate(distance);
+        (self.points[i + 1] - self.points[i]).normalized()
+    }
+
+    /// The segment containing `distance`, skipping zero length ones, and the
+    /// position within it.
+    fn locate(&self, distance: f32) -> (usize, f32) {
+        let distance = distance.clamp(0.0, self.length());
+        let end = self.distances.partition_point(|&d| d < distance).clamp(1, self.points.len() - 1);
+        let i = (1..=end).rev().find(|&i| self.distances[i] > self.distances[i - 1]).unwrap_or(end) - 1;
+        let span = self.distances[i + 1] - self.distances[i];
+        let t = if span > 0.0 { (distance - self.distances[i]) / span } else { 0.0 };
+        (i, t.clamp(0.0, 1.0))
+    }
+}
+
+/// How fast the character moves along the path, in scene units per second.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Speed {
+    Constant(f32),
+    /// `(frame, speed)` keys, interpolated linearly and held past the ends.
+    Keyed(Vec<(f32, f32)>),
+}
+
+impl Speed {
+    pub fn at(&self, frame: f32) -> f32 {
+        match self {
+            Speed::Constant(speed) => *speed,
+            Speed::Keyed(keys) => {
+                let next = keys.partition_point(|&(f, _)| f <= frame);
+                match (next.checked_sub(1).map(|i| keys[i]), keys.get(next)) {
+                    (Some((f0, s0)), Some(&(f1, s1))) => s0 + (s1 - s0) * (frame - f0) / (f1 - f0),
+                    (Some((_, s)), None) | (None, Some(&(_, s))) => s,
+                    (None, None) => 0.0,
+                }
+            }
+        }
+    }
+}
+
+/// Where the root is on the path at one frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathPose {
+    pub location: Vector3,
+    /// Rotation about Z from the rest pose (walking towards -Y) to the
+    /// direction of travel. Unwrapped, so it doesn't jump between frames.
+    pub heading: f32,
+    /// Distance travelled along the path.
+    pub distance: f32,
+    /// Speed over the last frame, in units per second. Zero once the root
+    /// reaches the end of the path.
+    pub speed: f32,
+}
+
+impl PathPose {
+    /// Moves `local` (relative to the root's rest position) along with the root.
+    pub fn transform(&self, local: Vector3) -> Vector3 {
+        let (s, c) = self.heading.sin_cos();
+        self.location + Vector3::new(local.x * c - local.y * s, local.x * s + local.y * c, local.z)
+    }
+}
+
+/// The root's pose at every frame, following a path at a given speed.
+#[derive(Clone, Debug)]
+pub struct RootMotion {
+    poses: Vec<PathPose>,
+}
+
+impl RootMotion {
+    /// Integrates `speed` over frames `0..=frames`. The root stops at the end
+    /// of the path.
+    pub fn new(path: &Path, speed: &Speed, frames: i32, fps: u32) -> Self {
+        let mut poses: Vec<PathPose> = Vec::with_capacity(frames.max(0) as usize + 1);
+        let mut distance = 0.0;
+        for frame in 0..=frames {
+            let previous = distance;
+            if frame > 0 {
+                let average = (speed.at(frame as f32 - 1.0) + speed.at(frame as f32)) * 0.5;
+                distance = (distance + average / fps as f32).clamp(0.0, path.length());
+            }
+            let tangent = path.tangent_at(distance);
+            let mut heading = if tangent.x != 0.0 || tangent.y != 0.0 {
+                tangent.y.atan2(tangent.x) + FRAC_PI_2
+            } else {
+                poses.last().map_or(0.0, |p| p.heading)
+            };
+            if let Some(previous) = poses.last() {
+                let turn = (heading - previous.heading + PI).rem_euclid(TAU) - PI;
+                heading = previous.heading + turn;
+            }
+            poses.push(PathPose {
+                location: path.point_at(distance),
+                heading,
+                distance,
+                speed: (distance - previous) * fps as f32,
+            });
+        }
+        Self { poses }
+    }
+
+    /// The pose at `frame`, clamped to the sampled range.
+    pub fn at(&self, frame: i32) -> PathPose {
+        self.poses[(frame.max(0) as usize).min(self.poses.len() - 1)]
+    }
+}
+
+fn bezier(p0: Vector3, c0: Vector3, c1: Vector3, p1: Vector3, t: f32) -> Vector3 {
+    let u = 1.0 - t;
+    p0 * (u * u * u) + c0 * (3.0 * u * u * t) + c1 * (3.0 * u * t * t) + p1 * (t * t * t)
+}
+
+/// Uniform Catmull-Rom between `p1` and `p2`.
+fn catmull_rom(p0: Vector3, p1: Vector3, p2: Vector3, p3: Vector3, t: f32) -> Vector3 {
+    let (t2, t3) = (t * t, t * t * t);
+    (p1 * 2.0 + (p2 - p0) * t + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2 + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polyline_length_is_exact() {
+        let path = Path::new(
+            PathKind::Polyline,
+            &[Vector3::new(0.0, 0.0, 0.0), Vector3::new(3.0, 4.0, 0.0), Vector3::new(3.0, 4.0, 2.0)],
+        )
+        .unwrap();
+        assert!((path.length() - 7.0).abs() < 1e-5);
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 1e-5;
+        assert!(close(path.point_at(5.0), Vector3::new(3.0, 4.0, 0.0)));
+        assert!(close(path.point_at(6.0), Vector3::new(3.0, 4.0, 1.0)));
+        // Clamped past the ends
+        assert!(close(path.point_at(-1.0), Vector3::new(0.0, 0.0, 0.0)));
+        assert!(close(path.point_at(100.0), Vector3::new(3.0, 4.0, 2.0)));
+    }
+
+    #[test]
+    fn bezier_paths_move_at_constant_speed() {
+        // Bunched up control points, so the curve's parameter runs far from
+        // arc length: slow at the start and fast at the end
+        let control = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.2, 0.0, 0.0),
+            Vector3::new(1.0, 3.0, 0.0),
+            Vector3::new(8.0, 4.0, 0.0),
+        ];
+        let path = Path::new(PathKind::Bezier, &control).unwrap();
+        assert!((path.point_at(0.0) - control[0]).length() < 1e-5);
+        assert!((path.point_at(path.length()) - control[3]).length() < 1e-4);
+
+        let steps = 200;
+        let step = path.length() / steps as f32;
+        for i in 0..steps {
+            let a = path.point_at(i as f32 * step);
+            let b = path.point_at((i + 1) as f32 * step);
+            let chord = (b - a).length();
+            assert!((chord - step).abs() < step * 0.02, "step {}: moved {} instead of {}", i, chord, step);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_direction_of_travel() {
+        let control = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -10.0, 0.0)];
+        let path = Path::new(PathKind::CatmullRom, &control).unwrap();
+        let tangent = path.tangent_at(path.length() / 2.0);
+        assert!((tangent - Vector3::new(0.0, -1.0, 0.0)).length() < 1e-4);
+    }
+}
@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::anim::{self, Bone, CameraAnimData, ObjAnimData, Track};
+use crate::audio::analysis::{Analysis, BASS};
+use crate::audio::sequencer::Song;
+use crate::config::{Config, Rig};
+use crate::math::{nearest_euler, Mat4, Quat};
+use crate::mocap::Clip;
+use crate::path::{PathPose, RootMotion, Speed};
+use crate::preview::{write_png, AnimatedPng, Preview};
+use crate::scene::{Music, Object, Vector3};
+use crate::tempo::TempoMap;
+use crate::{audio, bvh, gltf, scene, script};
+
+/// Everything the setup script loads from `output.anim_data`.
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    objects: &'a [ObjAnimData],
+    camera: CameraAnimData,
+    /// The armature, with `animation.rig = "armature"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bones: Option<Vec<Bone>>,
+}
+
+/// The sampled objects and the root motion they follow, from
+/// [`Pipeline::sample_animation`]. The camera tracks the same motion.
+pub struct Animation {
+    pub objects: Vec<ObjAnimData>,
+    pub motion: RootMotion,
+}
+
+/// Timings reported by [`Pipeline::benchmark_setup`].
+#[derive(Clone, Debug)]
+pub struct SetupBenchmark {
+    pub objects: usize,
+    pub frames: i32,
+    /// Keys written across all F-curves.
+    pub keys: usize,
+    /// Sampling and curve fitting in Rust.
+    pub sample_time: Duration,
+    /// Whole Blender setup run, including startup and saving.
+    pub blender_time: Duration,
+    /// Creating and filling the F-curves, as reported by the script.
+    pub animation_time: Option<Duration>,
+}
+
+/// Timings reported by [`Pipeline::benchmark_audio`].
+#[derive(Clone, Debug)]
+pub struct AudioBenchmark {
+    pub duration_secs: u32,
+    pub threads: usize,
+    /// Rendering the song's notes and buses on one thread.
+    pub serial_time: Duration,
+    /// Rendering them on every thread.
+    pub parallel_time: Duration,
+    /// The whole track, mixed and written to `bench_audio.wav`.
+    pub write_time: Duration,
+}
+
+impl AudioBenchmark {
+    pub fn speedup(&self) -> f64 {
+        self.serial_time.as_secs_f64() / self.parallel_time.as_secs_f64()
+    }
+}
+
+/// The render pipeline, split into independently callable stages:
+///
+/// 1. [`Pipeline::generate_audio`]
+/// 2. [`Pipeline::sample_animation`]
+/// 3. [`Pipeline::generate_script`]
+/// 4. [`Pipeline::setup_scene`]
+/// 5. [`Pipeline::render`]
+/// 6. [`Pipeline::concat`]
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    pub config: Config,
+}
+
+impl Pipeline {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Synthesizes the soundtrack into `audio.file`, sequencing the
+    /// `audio.pattern` song to the beat of `tempo`. Does nothing when
+    /// `audio.input` provides the soundtrack instead.
+    pub fn generate_audio(&self) -> std::io::Result<()> {
+        let config = &self.config.audio;
+        if config.input.is_some() {
+            return Ok(());
+        }
+        let song = self.song()?;
+        let tempo = self.tempo_map()?;
+        audio::generate_audio(
+            &config.file,
+            config.duration_secs,
+            &tempo,
+            &song,
+            &config.effects,
+            config.spec(),
+            self.config.seed,
+        )
+    }
+
+    /// The song the synth plays: `audio.pattern`, or the built-in groove.
+    fn song(&self) -> std::io::Result<Song> {
+        match &self.config.audio.pattern {
+            Some(pattern) => Song::load(pattern),
+            None => Ok(Song::default()),
+        }
+    }
+
+    /// Detects the beats, onsets and band energies of `audio.input`, if set.
+    pub fn analyze_audio(&self) -> std::io::Result<Option<Analysis>> {
+        self.config.audio.input.as_ref().map(Analysis::load).transpose()
+    }
+
+    /// The soundtrack at every frame: the beats detected in `audio.input`
+    /// and its bass, or the beats of `tempo` for the synthesized one.
+    pub fn music(&self) -> std::io::Result<Vec<Music>> {
+        let (frames, fps) = (self.config.animation.frames, self.config.animation.fps);
+        match self.analyze_audio()? {
+            Some(analysis) => {
+                Ok(analysis.frames(frames, fps).iter().map(|f| Music { beat: f.beat, bass: f.bands[BASS] }).collect())
+            }
+            None => {
+                let tempo = self.tempo_map()?;
+                Ok((0..=frames)
+                    .map(|frame| Music { beat: tempo.beat_at_frame(frame, fps) as f32, bass: 0.0 })
+                    .collect())
+            }
+        }
+    }
+
+    /// Samples the walk cycle for every frame and fits the samples to sparse
+    /// keys within `animation.tolerance`. Objects that carry their own
+    /// keyframes are exported as keyed instead.
+    ///
+    /// The character's pose comes from the `gaits` timeline, with the
+    /// walking gaits advancing with the distance walked along `path`, or
+    /// from the `mocap` clip when one is configured. Beat-synced effects
+    /// follow the [`Pipeline::music`], which is analyzed only once.
+    pub fn sample_animation(&self) -> std::io::Result<Animation> {
+        let animation = &self.config.animation;
+        let music = self.music()?;
+        let motion = self.root_motion(&music)?;
+
+        if let Some(file) = &self.config.mocap.file {
+            let clip = Clip::load(file, self.config.mocap.scale, animation.frames, animation.fps)?;
+            let objects = self.sample_with(&motion, |frame, _| clip.objects(frame, music[frame as usize]));
+            return Ok(Animation { objects, motion });
+        }
+
+        let poses = self.config.gait_timeline().poses(
+            animation.frames,
+            animation.fps,
+            |frame| music[frame as usize].beat,
+            |frame| motion.at(frame).speed,
+        );
+        let objects =
+            self.sample_with(&motion, |frame, _| scene::character(music[frame as usize], &poses[frame as usize]));
+        Ok(Animation { objects, motion })
+    }
+
+    /// Samples the objects returned by `objects_at(frame, root_pose)` for
+    /// every frame, like [`Pipeline::sample_animation`] does for the walk
+    /// cycle.
+    ///
+    /// Root objects are moved and turned along `motion` (their location is
+    /// taken relative to the path), children stay in their parent's space.
+    pub fn sample_with(
+        &self,
+        motion: &RootMotion,
+        objects_at: impl Fn(i32, &PathPose) -> Vec<Object>,
+    ) -> Vec<ObjAnimData> {
+        let frames = self.config.animation.frames;
+        let mut tracks: HashMap<String, Track> = HashMap::new();
+
+        // Initialize tracks with the procedural objects from frame 0
+        let initial_objects = objects_at(0, &motion.at(0));
+        for obj in initial_objects.iter().filter(|obj| obj.keyframes.is_empty()) {
+            tracks.insert(obj.name.clone(), Track::default());
+        }
+
+        // Loop through all frames and collect data
+        for frame in 0..=frames {
+            let root = motion.at(frame);
+            for mut obj in objects_at(frame, &root) {
+                if let Some(track) = tracks.get_mut(&obj.name) {
+                    if obj.parent.is_none() {
+                        obj.location = root.transform(obj.location);
+                        // Turn about the world Z axis, then back to the object's own Euler order
+                        let heading = Quat::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), root.heading);
+                        let rotation = heading * Quat::from_euler(obj.rotation, obj.rotation_order);
+                        let turned = Mat4::from_quat(rotation).to_euler(obj.rotation_order);
+                        // Continuous with the last frame instead of wrapping at +-pi
+                        obj.rotation = match track.last_rotation() {
+                            Some(previous) => nearest_euler(turned, previous),
+                            None => turned,
+                        };
+                    }
+                    track.push(&obj);
+                }
+            }
+        }
+
+        initial_objects
+            .iter()
+            .map(|obj| match tracks.remove(&obj.name) {
+                Some(track) => track.fit(obj, self.config.animation.tolerance),
+                None => ObjAnimData::keyed(obj),
+            })
+            .collect()
+    }
+
+    /// The soundtrack's tempo from `tempo`.
+    pub fn tempo_map(&self) -> std::io::Result<TempoMap> {
+        self.config.tempo.map().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
+
+    /// The root's pose along `path` for every frame, with `music` from
+    /// [`Pipeline::music`] for `path.beat_sync`.
+    pub fn root_motion(&self, music: &[Music]) -> std::io::Result<RootMotion> {
+        let path = self.config.path.path().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let (frames, fps) = (self.config.animation.frames, self.config.animation.fps);
+        let gaits = self.config.gait_timeline();
+        let speed = if self.config.mocap.file.is_some() {
+            // A clip's root carries its own travel, so the path only places
+            // where it starts and which way it faces
+            Speed::Constant(0.0)
+        } else if self.config.path.beat_sync {
+            // Half a stride of the current gait (one step) per beat, at the
+            // beat rate over the next frame
+            Speed::Keyed(
+                (0..=frames)
+                    .map(|frame| {
+                        let next = (frame + 1).min(frames) as usize;
+                        let beats = music[next].beat - music[next - 1].beat;
+                        (frame as f32, beats * fps as f32 * gaits.stride(frame) * 0.5)
+                    })
+                    .collect(),
+            )
+        } else {
+            // Gaits that stay in place stop the root, so their feet don't slide
+            let speed = self.config.path.speed();
+            Speed::Keyed((0..=frames).map(|f| (f as f32, speed.at(f as f32) * gaits.travel(f))).collect())
+        };
+        Ok(RootMotion::new(&path, &speed, frames, fps))
+    }
+
+    /// Keys the camera following the root of `animation` along `path`,
+    /// aimed at its first root object.
+    pub fn sample_camera(&self, animation: &Animation) -> CameraAnimData {
+        let motion = &animation.motion;
+        let locations: Vec<[f32; 3]> = (0..=self.config.animation.frames)
+            .map(|frame| {
+                let p = scene::camera_location(&motion.at(frame));
+                [p.x, p.y, p.z]
+            })
+            .collect();
+        let target = animation.objects.iter().find(|d| d.parent.is_none()).map(|d| d.name.clone());
+        CameraAnimData::sampled(target, &locations, self.config.animation.tolerance)
+    }
+
+    /// Writes the objects of `animation`, the camera track and the armature
+    /// bones (for the armature rig) to the `output.anim_data` sidecar and the
+    /// Blender setup script that loads it to `output.script`.
+    pub fn generate_script(&self, animation: &Animation) -> std::io::Result<()> {
+        let bones = match self.config.animation.rig {
+            Rig::Objects => None,
+            Rig::Armature => Some(anim::armature(&animation.objects)?),
+        };
+        let sidecar = Sidecar { objects: &animation.objects, camera: self.sample_camera(animation), bones };
+        let writer = BufWriter::new(File::create(&self.config.output.anim_data)?);
+        serde_json::to_writer(writer, &sidecar).map_err(std::io::Error::other)?;
+
+        let script = script::setup_script(&self.config);
+        let mut file = File::create(&self.config.output.script)?;
+        file.write_all(script.as_bytes())
+    }
+
+    /// Writes `anim_data` as a glTF binary to `output.gltf`.
+    pub fn export_gltf(&self, anim_data: &[ObjAnimData]) -> std::io::Result<()> {
+        gltf::write_glb(&self.config.output.gltf, anim_data, self.config.animation.frames, self.config.animation.fps)
+    }
+
+    /// Writes `anim_data` as BVH motion capture to `output.bvh`.
+    pub fn export_bvh(&self, anim_data: &[ObjAnimData]) -> std::io::Result<()> {
+        bvh::write_bvh(&self.config.output.bvh, anim_data, self.config.animation.frames, self.config.animation.fps)
+    }
+
+    /// Renders every frame with the software rasterizer into
+    /// `preview.frames_dir`, plus an animated PNG at `preview.animation`.
+    pub fn preview(&self, animation: &Animation) -> std::io::Result<()> {
+        let config = &self.config.preview;
+        let frames = self.config.animation.frames;
+        let camera = self.sample_camera(animation);
+        let preview = Preview::new(&animation.objects, &camera, config.width, config.height)?;
+        let dir = Path::new(&config.frames_dir);
+        std::fs::create_dir_all(dir)?;
+        let mut animation = AnimatedPng::create(
+            &config.animation,
+            config.width,
+            config.height,
+            frames as u32 + 1,
+            self.config.animation.fps,
+        )?;
+
+        let pb = ProgressBar::new(frames as u64 + 1);
+        pb.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+
+        // Render in parallel batches, appending to the animation in order
+        let batch = rayon::current_num_threads() as i32 * 4;
+        for start in (0..=frames).step_by(batch as usize) {
+            let end = (start + batch).min(frames + 1);
+            let images = (start..end)
+                .into_par_iter()
+                .map(|frame| {
+                    let rgb = preview.render(frame).to_rgb8();
+                    write_png(dir.join(format!("frame_{:04}.png", frame)), config.width, config.height, &rgb)?;
+                    pb.inc(1);
+                    Ok(rgb)
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            for rgb in images {
+                animation.push(&rgb)?;
+            }
+        }
+        pb.finish_with_message("Done");
+        animation.finish()
+    }
+
+    /// Runs the setup script in Blender (single thread), producing `output.blend_file`.
+    pub fn setup_scene(&self) -> std::io::Result<()> {
+        let blender_bin = self.blender()?;
+        let status = Command::new(&blender_bin).arg("-b").arg("-P").arg(&self.config.output.script).status()?;
+
+        if !status.success() {
+            return Err(std::io::Error::other("Failed to setup scene"));
+        }
+        Ok(())
+    }
+
+    /// Renders `output.blend_file` in `render.chunks` parallel Blender processes, producing
+    /// `part_<chunk>_<start>-<end>.mp4` files in `output.chunks_dir`.
+    pub fn render(&self) -> std::io::Result<()> {
+        let blender_bin = self.blender()?;
+        let chunks_dir = self.chunks_dir()?;
+        let frames = self.config.animation.frames;
+        let chunks = self.config.render.chunks;
+        let frames_per_chunk = frames / chunks;
+        let mut handles = vec![];
+        let m = MultiProgress::new();
+        let sty = ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+            .unwrap()
+            .progress_chars("##-");
+
+        for i in 0..chunks {
+            let start_frame = i * frames_per_chunk;
+            let end_frame = if i == chunks - 1 { frames } else { (i + 1) * frames_per_chunk - 1 };
+            let blender_bin = blender_bin.clone();
+            let blend_file = self.config.output.blend_file.clone();
+            // Absolute, since Blender takes relative paths from the .blend file's directory
+            let output_path = chunks_dir.join(format!("part_{}_", i));
+            let pb = m.add(ProgressBar::new((end_frame - start_frame + 1) as u64));
+            pb.set_message(format!("Chunk {}", i));
+            pb.set_style(sty.clone());
+
+            let handle = thread::spawn(move || -> std::io::Result<()> {
+                // Output filename: part_X_####.mp4
+                // Blender appends the frame range for FFMPEG output,
+                // e.g. "part_0_0000-0449.mp4".
+                let mut cmd = Command::new(&blender_bin)
+                    .arg("-b")
+                    .arg(&blend_file)
+                    .arg("-o")
+                    .arg(&output_path)
+                    .arg("-s")
+                    .arg(start_frame.to_string())
+                    .arg("-e")
+                    .arg(end_frame.to_string())
+                    .arg("-a") // Render animation
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                let stdout = cmd.stdout.take().unwrap();
+                let reader = BufReader::new(stdout);
+
+                for l in reader.lines().map_while(Result::ok) {
+                    if l.contains("Append frame") {
+                        pb.inc(1);
+                    }
+                }
+
+                let status = cmd.wait()?;
+                pb.finish_with_message("Done");
+                if !status.success() {
+                    return Err(std::io::Error::other(format!("Blender worker {} failed", i)));
+                }
+                Ok(())
+            });
+            handles.push(handle);
+        }
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|_| Err(std::io::Error::other("Blender worker panicked"))))
+            .collect();
+        m.clear().unwrap();
+        results.into_iter().collect()
+    }
+
+    /// Joins the rendered chunks in `output.chunks_dir` into `output.video`
+    /// using Blender's sequencer.
+    pub fn concat(&self) -> std::io::Result<()> {
+        let chunks_dir = self.chunks_dir()?;
+        let script_path = chunks_dir.join("concat_script.py");
+        let mut file = File::create(&script_path)?;
+        file.write_all(script::concat_script(&self.config, &chunks_dir).as_bytes())?;
+
+        let blender_bin = self.blender()?;
+        let status = Command::new(blender_bin).arg("-b").arg("-P").arg(script_path).status()?;
+
+        if !status.success() {
+            return Err(std::io::Error::other("Failed to concatenate video parts"));
+        }
+        Ok(())
+    }
+
+    /// `output.chunks_dir` as an absolute path, created if needed, so the
+    /// render workers and the concat script agree on it wherever they run.
+    fn chunks_dir(&self) -> std::io::Result<PathBuf> {
+        let dir = &self.config.output.chunks_dir;
+        std::fs::create_dir_all(dir)?;
+        std::path::absolute(dir)
+    }
+
+    /// Times the Blender setup step for a synthetic scene of `objects`
+    /// animated cubes over `animation.frames` frames.
+    ///
+    /// The benchmark writes its own `bench_*` script, sidecar and .blend
+    /// file, so it doesn't clobber the real scene.
+    pub fn benchmark_setup(&self, objects: usize) -> std::io::Result<SetupBenchmark> {
+        let mut bench = self.clone();
+        bench.config.output.script = "bench_setup_scene.py".to_string();
+        bench.config.output.anim_data = "bench_anim_data.json".to_string();
+        bench.config.output.blend_file = "bench_scene.blend".to_string();
+        if !Path::new(bench.config.audio.track()).exists() {
+            bench.generate_audio()?;
+        }
+
+        let start = Instant::now();
+        let motion = bench.root_motion(&bench.music()?)?;
+        let anim_data = bench.sample_with(&motion, |frame, _| scene::benchmark_scene(frame, objects));
+        let sample_time = start.elapsed();
+        let keys = anim_data.iter().flat_map(|d| &d.channels).map(|c| c.keys.keys.len()).sum();
+        bench.generate_script(&Animation { objects: anim_data, motion })?;
+
+        let blender_bin = bench.blender()?;
+        let start = Instant::now();
+        let output = Command::new(&blender_bin).arg("-b").arg("-P").arg(&bench.config.output.script).output()?;
+        let blender_time = start.elapsed();
+
+        if !output.status.success() {
+            return Err(std::io::Error::other("Failed to setup benchmark scene"));
+        }
+        let animation_time = String::from_utf8_lossy(&output.stdout).lines().find_map(|l| {
+            let secs = l.strip_prefix("GHOSTRENDER_ANIMATION_SECONDS=")?;
+            secs.trim().parse().ok().map(Duration::from_secs_f64)
+        });
+
+        Ok(SetupBenchmark {
+            objects,
+            frames: bench.config.animation.frames,
+            keys,
+            sample_time,
+            blender_time,
+            animation_time,
+        })
+    }
+
+    /// Times the synth rendering `duration_secs` of the song on one thread
+    /// and on every thread, best of two runs, checking both give the same
+    /// samples, then writes the whole track to `bench_audio.wav`. Both
+    /// renders are streamed side by side, a window at a time.
+    pub fn benchmark_audio(&self, duration_secs: u32) -> std::io::Result<AudioBenchmark> {
+        let config = &self.config.audio;
+        let song = self.song()?;
+        let tempo = self.tempo_map()?;
+        let stream = || song.stream(&tempo, config.sample_rate, duration_secs, self.config.seed);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().map_err(std::io::Error::other)?;
+        let (mut serial_time, mut parallel_time) = (Duration::MAX, Duration::MAX);
+        // Best of two, so neither pays alone for warming up the allocator
+        for _ in 0..2 {
+            let start = Instant::now();
+            let mut serial = pool.install(stream);
+            let mut serial_run = start.elapsed();
+            let start = Instant::now();
+            let mut parallel = stream();
+            let mut parallel_run = start.elapsed();
+            loop {
+                let start = Instant::now();
+                let expected = pool.install(|| serial.next());
+                serial_run += start.elapsed();
+
+                let start = Instant::now();
+                let window = parallel.next();
+                parallel_run += start.elapsed();
+                if window != expected {
+                    return Err(std::io::Error::other("parallel audio rendering differs from serial"));
+                }
+                if window.is_none() {
+                    break;
+                }
+            }
+            serial_time = serial_time.min(serial_run);
+            parallel_time = parallel_time.min(parallel_run);
+        }
+
+        let start = Instant::now();
+        let (spec, seed) = (config.spec(), self.config.seed);
+        audio::generate_audio("bench_audio.wav", duration_secs, &tempo, &song, &config.effects, spec, seed)?;
+        let write_time = start.elapsed();
+
+        Ok(AudioBenchmark {
+            duration_secs,
+            threads: rayon::current_num_threads(),
+            serial_time,
+            parallel_time,
+            write_time,
+        })
+    }
+
+    /// The configured Blender executable, or the first one found on the system.
+    fn blender(&self) -> std::io::Result<String> {
+        match &self.config.render.blender {
+            Some(path) => Ok(path.clone()),
+            None => {
+                find_blender().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Blender not found"))
+            }
+        }
+    }
+}
+
+/// Looks for a Blender executable in `PATH` and the usual install locations.
+pub fn find_blender() -> Option<String> {
+    let paths = vec![
+        "blender",
+        "/Applications/Blender.app/Contents/MacOS/Blender",
+        "/usr/bin/blender",
+        "C:\\Program Files\\Blender Foundation\\Blender 3.6\\blender.exe",
+    ];
+    for path in paths {
+        if Command::new(path).arg("--version").output().is_ok() {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,402 @@
+//! CPU software rasterizer for previewing the animation without Blender.
+//!
+//! Draws the same scene as the setup script (objects, road and grid lines)
+//! from the camera track, with Lambert shading from the default scene
+//! light, emission and a simple bloom. Good enough to check the motion on a
+//! machine without a GPU or Blender; the look only approximates EEVEE.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::anim::{CameraAnimData, ObjAnimData, ObjState};
+use crate::math::Mat4;
+use crate::mesh::Mesh;
+use crate::scene::{TransformGraph, Vector3};
+
+/// Horizontal field of view of Blender's default 50mm lens on a 36mm sensor.
+const HALF_FOV_TAN: f32 = 18.0 / 50.0;
+const NEAR: f32 = 0.1;
+/// Position of the point light in Blender's default startup scene.
+const LIGHT: Vector3 = Vector3 { x: 4.076, y: 1.005, z: 5.904 };
+/// Ambient light, matching the default world color.
+const AMBIENT: f32 = 0.05;
+const BLOOM_THRESHOLD: f32 = 0.8;
+const BLOOM_INTENSITY: f32 = 0.3;
+
+/// A linear HDR image.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize, color: [f32; 3]) -> Self {
+        Self { width, height, pixels: vec![color; width * height] }
+    }
+
+    /// Tonemapped 8-bit sRGB pixels, row by row.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| p.map(|c| (srgb(1.0 - (-c).exp()) * 255.0 + 0.5) as u8)).collect()
+    }
+}
+
+/// One mesh with its shading parameters, ready to draw.
+struct Drawable<'a> {
+    mesh: &'a Mesh,
+    world: Mat4,
+    color: [f32; 3],
+    emission: f32,
+}
+
+/// Renders frames of the sampled animation.
+pub struct Preview<'a> {
+    anim_data: &'a [ObjAnimData],
+    camera: &'a CameraAnimData,
+    /// Index of the object the camera is aimed at.
+    target: Option<usize>,
+    meshes: Vec<Mesh>,
+    graph: TransformGraph,
+    environment: Vec<(Mesh, [f32; 3], f32)>,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Preview<'a> {
+    pub fn new(
+        anim_data: &'a [ObjAnimData],
+        camera: &'a CameraAnimData,
+        width: u32,
+        height: u32,
+    ) -> std::io::Result<Self> {
+        let graph = TransformGraph::new(anim_data.iter().map(|d| (d.name.as_str(), d.parent.as_deref())))?;
+        let meshes = anim_data
+            .iter()
+            .map(|data| {
+                let mut mesh = Mesh::primitive(&data.object_type).ok_or_else(|| {
+                    invalid(format!("unsupported object_type {} for {}", data.object_type, data.name))
+                })?;
+                mesh.scale(data.scale);
+                mesh.translate(data.pivot.map(|v| -v));
+                Ok(mesh)
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let target = match &camera.target {
+            Some(name) => Some(
+                anim_data
+                    .iter()
+                    .position(|d| &d.name == name)
+                    .ok_or_else(|| invalid(format!("camera target {} not found", name)))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            anim_data,
+            camera,
+            target,
+            meshes,
+            graph,
+            environment: environment(),
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    /// Renders `frame` from the scene camera.
+    pub fn render(&self, frame: i32) -> Image {
+        let states: Vec<ObjState> = self.anim_data.iter().map(|d| d.state_at(frame as f32)).collect();
+        let local: Vec<Mat4> = states.iter().map(ObjState::local_matrix).collect();
+        let worlds = self.graph.world_matrices(&local);
+
+        let target = self.target.map_or(Vector3::new(0.0, 0.0, 0.0), |i| worlds[i].translation());
+        let eye = self.camera.location_at(frame as f32);
+        let view = Mat4::look_at(eye, target, Vector3::new(0.0, 0.0, 1.0));
+
+        let mut drawables: Vec<Drawable> = self
+            .environment
+            .iter()
+            .map(|(mesh, color, emission)| Drawable { mesh, world: Mat4::IDENTITY, color: *color, emission: *emission })
+            .collect();
+        for (i, state) in states.iter().enumerate() {
+            if state.visible {
+                let c = state.color;
+                drawables.push(Drawable {
+                    mesh: &self.meshes[i],
+                    world: worlds[i],
+                    color: [c.r, c.g, c.b],
+                    emission: state.emission,
+                });
+            }
+        }
+
+        let mut raster = Raster::new(self.width, self.height);
+        for drawable in &drawables {
+            raster.draw(drawable, view);
+        }
+        bloom(&mut raster.image);
+        raster.image
+    }
+}
+
+/// The road and grid lines built by the setup script, in world space.
+fn environment() -> Vec<(Mesh, [f32; 3], f32)> {
+    let mut road = Mesh::plane();
+    road.scale([10.0, 1000.0, 1.0]);
+    let mut items = vec![(road, [0.05, 0.05, 0.05], 0.0)];
+
+    for i in -20..20 {
+        let mut line = Mesh::cube();
+        line.scale([0.05, 100.0, 0.05]);
+        for p in &mut line.positions {
+            p[0] += i as f32 * 2.0;
+            p[2] -= 0.1;
+        }
+        items.push((line, [0.0, 1.0, 0.8], 5.0));
+    }
+    items
+}
+
+/// A color buffer with a depth buffer holding `1 / depth`.
+struct Raster {
+    image: Image,
+    depth: Vec<f32>,
+    focal: f32,
+}
+
+impl Raster {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            image: Image::new(width, height, [AMBIENT; 3]),
+            depth: vec![0.0; width * height],
+            focal: width as f32 * 0.5 / HALF_FOV_TAN,
+        }
+    }
+
+    fn draw(&mut self, drawable: &Drawable, view: Mat4) {
+        let mesh = drawable.mesh;
+        let world: Vec<Vector3> =
+            mesh.positions.iter().map(|p| drawable.world.transform_point(Vector3::new(p[0], p[1], p[2]))).collect();
+
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| world[i as usize]);
+            let normal = (b - a).cross(c - a).normalized();
+            let center = (a + b + c) * (1.0 / 3.0);
+            let diffuse = normal.dot((LIGHT - center).normalized()).max(0.0);
+            let color = drawable.color.map(|c| c * (AMBIENT + diffuse + drawable.emission));
+
+            let polygon = clip_near([a, b, c].map(|p| view.transform_point(p)));
+            let projected: Vec<[f32; 3]> = polygon.iter().map(|&p| self.project(p)).collect();
+            for i in 1..projected.len().saturating_sub(1) {
+                self.fill([projected[0], projected[i], projected[i + 1]], color);
+            }
+        }
+    }
+
+    /// Screen position and `1 / depth` of a view space point.
+    fn project(&self, p: Vector3) -> [f32; 3] {
+        let inv_depth = 1.0 / -p.z;
+        [
+            self.image.width as f32 * 0.5 + self.focal * p.x * inv_depth,
+            self.image.height as f32 * 0.5 - self.focal * p.y * inv_depth,
+            inv_depth,
+        ]
+    }
+
+    fn fill(&mut self, v: [[f32; 3]; 3], color: [f32; 3]) {
+        // Counter-clockwise faces wind clockwise once y points down; the
+        // meshes are closed, so the others are back faces
+        let area = edge(v[0], v[1], v[2]);
+        if area >= 0.0 {
+            return;
+        }
+        let (w, h) = (self.image.width, self.image.height);
+        let min_x = v.iter().map(|p| p[0]).fold(f32::INFINITY, f32::min).floor().max(0.0) as usize;
+        let max_x = v.iter().map(|p| p[0]).fold(f32::NEG_INFINITY, f32::max).ceil().min(w as f32) as usize;
+        let min_y = v.iter().map(|p| p[1]).fold(f32::INFINITY, f32::min).floor().max(0.0) as usize;
+        let max_y = v.iter().map(|p| p[1]).fold(f32::NEG_INFINITY, f32::max).ceil().min(h as f32) as usize;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = [x as f32 + 0.5, y as f32 + 0.5, 0.0];
+                let w0 = edge(v[1], v[2], p) / area;
+                let w1 = edge(v[2], v[0], p) / area;
+                let w2 = edge(v[0], v[1], p) / area;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+                // 1 / depth is linear in screen space
+                let inv_depth = w0 * v[0][2] + w1 * v[1][2] + w2 * v[2][2];
+                let i = y * w + x;
+                if inv_depth > self.depth[i] {
+                    self.depth[i] = inv_depth;
+                    self.image.pixels[i] = color;
+                }
+            }
+        }
+    }
+}
+
+fn edge(a: [f32; 3], b: [f32; 3], p: [f32; 3]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Clips a view space triangle against the near plane.
+fn clip_near(triangle: [Vector3; 3]) -> Vec<Vector3> {
+    let inside = |p: Vector3| -p.z >= NEAR;
+    let mut polygon = Vec::with_capacity(4);
+    for i in 0..3 {
+        let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+        if inside(a) {
+            polygon.push(a);
+        }
+        if inside(a) != inside(b) {
+            let t = (-NEAR - a.z) / (b.z - a.z);
+            polygon.push(a + (b - a) * t);
+        }
+    }
+    polygon
+}
+
+/// Adds a blurred copy of the bright parts of the image, like EEVEE bloom.
+fn bloom(image: &mut Image) {
+    // Blur at quarter resolution; three box passes approximate a gaussian
+    let (w, h) = (image.width.div_ceil(4), image.height.div_ceil(4));
+    let mut bright = vec![[0.0f32; 3]; w * h];
+    for (i, p) in image.pixels.iter().enumerate() {
+        let (x, y) = ((i % image.width) / 4, (i / image.width) / 4);
+        let b = &mut bright[y * w + x];
+        for c in 0..3 {
+            b[c] += (p[c] - BLOOM_THRESHOLD).max(0.0) / 16.0;
+        }
+    }
+
+    let radius = (h / 24).max(1);
+    for _ in 0..3 {
+        bright = box_blur(&bright, w, h, radius, true);
+        bright = box_blur(&bright, w, h, radius, false);
+    }
+
+    for (i, p) in image.pixels.iter_mut().enumerate() {
+        let (x, y) = ((i % image.width) / 4, (i / image.width) / 4);
+        let b = bright[y * w + x];
+        for c in 0..3 {
+            p[c] += b[c] * BLOOM_INTENSITY;
+        }
+    }
+}
+
+fn box_blur(src: &[[f32; 3]], w: usize, h: usize, radius: usize, horizontal: bool) -> Vec<[f32; 3]> {
+    let (len, lines) = if horizontal { (w, h) } else { (h, w) };
+    let at = |line: usize, i: usize| if horizontal { line * w + i } else { i * w + line };
+    let mut dst = vec![[0.0; 3]; src.len()];
+    let scale = 1.0 / (2 * radius + 1) as f32;
+
+    for line in 0..lines {
+        for i in 0..len {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(len);
+            let mut sum = [0.0; 3];
+            for j in start..end {
+                let p = src[at(line, j)];
+                for c in 0..3 {
+                    sum[c] += p[c];
+                }
+            }
+            dst[at(line, i)] = sum.map(|s| s * scale);
+        }
+    }
+    dst
+}
+
+fn srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Writes 8-bit RGB pixels as a PNG file.
+pub fn write_png(path: impl AsRef<Path>, width: u32, height: u32, rgb: &[u8]) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+    writer.write_image_data(rgb).map_err(std::io::Error::other)?;
+    writer.finish().map_err(std::io::Error::other)
+}
+
+/// An animated PNG written one frame at a time.
+pub struct AnimatedPng {
+    writer: png::Writer<BufWriter<File>>,
+}
+
+impl AnimatedPng {
+    pub fn create(path: impl AsRef<Path>, width: u32, height: u32, frames: u32, fps: u32) -> std::io::Result<Self> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames, 0).map_err(std::io::Error::other)?;
+        encoder.set_frame_delay(1, u16::try_from(fps).unwrap_or(u16::MAX)).map_err(std::io::Error::other)?;
+        let writer = encoder.write_header().map_err(std::io::Error::other)?;
+        Ok(Self { writer })
+    }
+
+    pub fn push(&mut self, rgb: &[u8]) -> std::io::Result<()> {
+        self.writer.write_image_data(rgb).map_err(std::io::Error::other)
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        self.writer.finish().map_err(std::io::Error::other)
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit cube at the origin seen from 5 units down -Y, on a 64x64 raster.
+    fn draw_cube(color: [f32; 3], emission: f32) -> Image {
+        let cube = Mesh::cube();
+        let view =
+            Mat4::look_at(Vector3::new(0.0, -5.0, 0.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut raster = Raster::new(64, 64);
+        raster.draw(&Drawable { mesh: &cube, world: Mat4::IDENTITY, color, emission }, view);
+        raster.image
+    }
+
+    fn pixel(image: &Image, x: usize, y: usize) -> [f32; 3] {
+        image.pixels[y * image.width + x]
+    }
+
+    #[test]
+    fn covered_pixels_take_the_object_color() {
+        let image = draw_cube([1.0, 0.0, 0.0], 0.0);
+        let center = pixel(&image, 32, 32);
+        assert!(center[0] > 0.0 && center[1] == 0.0 && center[2] == 0.0, "{:?}", center);
+        // The near face is 20 pixels across at this distance; the rest is clear
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63), (32, 5), (5, 32)] {
+            assert_eq!(pixel(&image, x, y), [AMBIENT; 3], "({}, {})", x, y);
+        }
+        let covered = image.pixels.iter().filter(|&&p| p != [AMBIENT; 3]).count();
+        assert!((300..500).contains(&covered), "{} pixels", covered);
+    }
+
+    #[test]
+    fn emission_brightens_the_object() {
+        let color = [0.2, 0.4, 0.8];
+        let plain = pixel(&draw_cube(color, 0.0), 32, 32);
+        let glowing = pixel(&draw_cube(color, 2.0), 32, 32);
+        // Emission adds the object color on top of the shading, keeping the hue
+        for c in 0..3 {
+            assert!((glowing[c] - plain[c] - 2.0 * color[c]).abs() < 1e-5, "{:?} {:?}", plain, glowing);
+        }
+    }
+}
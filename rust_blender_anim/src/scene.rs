@@ -1,6 +1,16 @@
+use std::collections::HashMap;
 use std::f32::consts::PI;
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
 
-#[derive(Clone, Copy)]
+use crate::curve::Interpolation;
+use crate::gait::{Gait, Pose, Walk};
+use crate::ik::TwoBone;
+use crate::math::{EulerOrder, Mat4, Quat};
+use crate::path::PathPose;
+use crate::tempo::TempoMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Vector3 {
     pub x: f32,
     pub y: f32,
@@ -11,10 +21,54 @@ impl Vector3 {
     pub fn new(x: f32, y: f32, z: f32) -> Self {
         Self { x, y, z }
     }
+
+    pub fn dot(self, o: Vector3) -> f32 {
+        self.x * o.x + self.y * o.y + self.z * o.z
+    }
+
+    pub fn cross(self, o: Vector3) -> Vector3 {
+        Vector3::new(self.y * o.z - self.z * o.y, self.z * o.x - self.x * o.z, self.x * o.y - self.y * o.x)
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(self) -> Vector3 {
+        let len = self.length();
+        if len > 0.0 {
+            self * (1.0 / len)
+        } else {
+            self
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
-#[allow(dead_code)]
+impl Add for Vector3 {
+    type Output = Vector3;
+
+    fn add(self, o: Vector3) -> Vector3 {
+        Vector3::new(self.x + o.x, self.y + o.y, self.z + o.z)
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Vector3;
+
+    fn sub(self, o: Vector3) -> Vector3 {
+        Vector3::new(self.x - o.x, self.y - o.y, self.z - o.z)
+    }
+}
+
+impl Mul<f32> for Vector3 {
+    type Output = Vector3;
+
+    fn mul(self, s: f32) -> Vector3 {
+        Vector3::new(self.x * s, self.y * s, self.z * s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -28,47 +82,253 @@ impl Color {
     }
 }
 
-#[allow(dead_code)]
+#[derive(Clone)]
 pub struct Object {
     pub name: String,
-    pub object_type: String, // "CUBE", "CYLINDER", etc.
+    pub object_type: String, // "CUBE", "CYLINDER" or "SPHERE"
     pub location: Vector3,
     pub rotation: Vector3,
+    pub rotation_order: EulerOrder,
     pub scale: Vector3,
+    /// The object's origin (the joint it rotates about) relative to the
+    /// center of its mesh, in scene units.
+    pub pivot: Vector3,
     pub color: Color,
+    pub emission: f32, // Emission strength of the generated material
+    pub visible: bool,
     pub parent: Option<String>,
     pub keyframes: Vec<Keyframe>,
 }
 
-#[allow(dead_code)]
+impl Object {
+    /// Transform relative to the parent (or the world for roots). `scale` is
+    /// baked into the mesh, so it isn't part of the transform and children
+    /// don't inherit it.
+    pub fn local_matrix(&self) -> Mat4 {
+        let unit = Vector3::new(1.0, 1.0, 1.0);
+        Mat4::from_trs(self.location, Quat::from_euler(self.rotation, self.rotation_order), unit)
+    }
+}
+
+/// A sparse key on an object. Objects with keyframes are exported as-is
+/// instead of being sampled every frame.
+#[derive(Clone, Default)]
 pub struct Keyframe {
     pub frame: i32,
+    /// Interpolation towards the next keyframe.
+    pub interpolation: Interpolation,
+    /// Bezier handles; automatic ones are used when unset.
+    pub handles: Option<KeyHandles>,
     pub location: Option<Vector3>,
     pub rotation: Option<Vector3>,
+    pub scale: Option<Vector3>,
+    pub color: Option<Color>,
+    pub emission: Option<f32>,
+    pub visible: Option<bool>,
+}
+
+/// Hand-set Bezier handles of a [`Keyframe`], as `[frames, value]` offsets
+/// from the key shared by every value it keys. `left: [-5.0, 0.0]` and
+/// `right: [5.0, 0.0]` ease in and out over 5 frames, for example. The
+/// handles shouldn't reach past the neighbouring keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyHandles {
+    pub left: [f32; 2],
+    pub right: [f32; 2],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransformError {
+    DuplicateName(String),
+    MissingParent {
+        object: String,
+        parent: String,
+    },
+    /// The names along the cycle, starting and ending with the same object.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransformError::DuplicateName(name) => write!(f, "duplicate object name {}", name),
+            TransformError::MissingParent { object, parent } => {
+                write!(f, "{} has missing parent {}", object, parent)
+            }
+            TransformError::Cycle(names) => write!(f, "parent cycle: {}", names.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+impl From<TransformError> for std::io::Error {
+    fn from(e: TransformError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
 }
 
+/// The parent hierarchy of a set of objects, validated once so world
+/// matrices can be composed every frame.
+#[derive(Clone, Debug)]
+pub struct TransformGraph {
+    parents: Vec<Option<usize>>,
+    /// Node indices with every parent before its children.
+    order: Vec<usize>,
+}
+
+impl TransformGraph {
+    /// Builds the graph from `(name, parent)` pairs. Node indices follow the
+    /// input order.
+    pub fn new<'a>(nodes: impl IntoIterator<Item = (&'a str, Option<&'a str>)>) -> Result<Self, TransformError> {
+        let nodes: Vec<(&str, Option<&str>)> = nodes.into_iter().collect();
+        let mut index = HashMap::with_capacity(nodes.len());
+        for (i, (name, _)) in nodes.iter().enumerate() {
+            if index.insert(*name, i).is_some() {
+                return Err(TransformError::DuplicateName(name.to_string()));
+            }
+        }
+
+        let parents = nodes
+            .iter()
+            .map(|(name, parent)| match parent {
+                Some(parent) => index.get(parent).copied().map(Some).ok_or_else(|| TransformError::MissingParent {
+                    object: name.to_string(),
+                    parent: parent.to_string(),
+                }),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Walk up from each node until reaching one already ordered; the
+        // path is then appended parents first
+        let mut ordered = vec![false; nodes.len()];
+        let mut order = Vec::with_capacity(nodes.len());
+        for start in 0..nodes.len() {
+            let mut path: Vec<usize> = Vec::new();
+            let mut node = Some(start);
+            while let Some(i) = node.filter(|&i| !ordered[i]) {
+                if let Some(pos) = path.iter().position(|&p| p == i) {
+                    let mut cycle: Vec<String> = path[pos..].iter().map(|&p| nodes[p].0.to_string()).collect();
+                    cycle.push(nodes[i].0.to_string());
+                    return Err(TransformError::Cycle(cycle));
+                }
+                path.push(i);
+                node = parents[i];
+            }
+            for &i in path.iter().rev() {
+                ordered[i] = true;
+                order.push(i);
+            }
+        }
+
+        Ok(Self { parents, order })
+    }
+
+    pub fn from_objects(objects: &[Object]) -> Result<Self, TransformError> {
+        Self::new(objects.iter().map(|o| (o.name.as_str(), o.parent.as_deref())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    pub fn parent(&self, node: usize) -> Option<usize> {
+        self.parents[node]
+    }
+
+    /// Composes parent chains: each world matrix is the parent's world
+    /// matrix times the node's `local` matrix.
+    pub fn world_matrices(&self, local: &[Mat4]) -> Vec<Mat4> {
+        assert_eq!(local.len(), self.len(), "one local matrix per node");
+        let mut world = local.to_vec();
+        for &i in &self.order {
+            if let Some(parent) = self.parents[i] {
+                world[i] = world[parent] * local[i];
+            }
+        }
+        world
+    }
+}
+
+/// World matrices of `objects`, composing their parent chains.
+pub fn world_matrices(objects: &[Object]) -> Result<Vec<Mat4>, TransformError> {
+    let graph = TransformGraph::from_objects(objects)?;
+    let local: Vec<Mat4> = objects.iter().map(Object::local_matrix).collect();
+    Ok(graph.world_matrices(&local))
+}
+
+/// Camera position relative to the character's root, before it turns with
+/// the path: behind, to the side and above.
+pub const CAMERA_OFFSET: Vector3 = Vector3 { x: 5.0, y: 8.0, z: 3.0 };
+
+/// Camera position following the character's root along its path.
+pub fn camera_location(root: &PathPose) -> Vector3 {
+    root.transform(CAMERA_OFFSET)
+}
+
+/// The walk cycle at `frame`, walking in place at the default pace of one
+/// cycle every 60 frames.
 pub fn calculate_walk_cycle(frame: i32, _total_frames: i32) -> Vec<Object> {
-    // 120 BPM = 2 beats/sec.
-    // At 60 FPS, 1 beat = 30 frames.
-    // A full walk cycle (left step + right step) = 2 beats = 60 frames.
-    // So we want phase to go 0..2PI every 60 frames.
-    let phase = frame as f32 * (2.0 * PI / 60.0);
+    // A full walk cycle (left step + right step) is two beats of the
+    // default tempo, 60 frames at 60 FPS.
+    walk_cycle(frame, frame as f32 / 60.0)
+}
+
+/// The walk cycle after `cycles` full cycles (left step + right step), with
+/// the default [`Walk`] parameters and tempo, at 60 FPS.
+pub fn walk_cycle(frame: i32, cycles: f32) -> Vec<Object> {
+    let beat = TempoMap::default().beat_at_frame(frame, 60) as f32;
+    character(Music { beat, bass: 0.0 }, &Walk::default().pose(cycles, beat))
+}
+
+/// The soundtrack at one frame, driving the beat-synced effects.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Music {
+    /// Beats since the start, fractional.
+    pub beat: f32,
+    /// Bass energy from 0 to 1, when analyzing a track.
+    pub bass: f32,
+}
+
+/// The kick drum's decay `beat` beats into the soundtrack: 1 on the beat,
+/// fading like the synth's kick.
+pub fn kick_envelope(beat: f32) -> f32 {
+    (-beat.rem_euclid(1.0) * 10.0).exp()
+}
 
+/// Left hip joint relative to the torso; the right one is mirrored in X.
+pub const HIP: Vector3 = Vector3 { x: 0.2, y: 0.0, z: -0.4 };
+/// Left shoulder joint relative to the torso.
+pub const SHOULDER: Vector3 = Vector3 { x: 0.33, y: 0.0, z: 0.3 };
+/// Thigh and shin lengths.
+pub const LEG: TwoBone = TwoBone { upper: 0.9, lower: 0.9 };
+/// Upper arm and forearm lengths.
+pub const ARM: TwoBone = TwoBone { upper: 0.5, lower: 0.5 };
+
+/// The character's objects in `pose`, with the limbs pulsing on the beat
+/// and the torso glowing with the bass of `music`.
+pub fn character(music: Music, pose: &Pose) -> Vec<Object> {
     let mut objects = Vec::new();
 
     // Root / Torso
-    let torso_y = (phase * 2.0).sin() * 0.1; // Bobbing
-    let torso_rot_z = phase.cos() * 0.1; // Swaying
-    
     objects.push(Object {
         name: "Torso".to_string(),
         object_type: "CUBE".to_string(),
-        location: Vector3::new(0.0, 0.0, 2.0 + torso_y),
-        rotation: Vector3::new(0.0, 0.0, torso_rot_z),
+        location: Vector3::new(0.0, 0.0, 2.0 + pose.height),
+        rotation: pose.torso,
+        rotation_order: EulerOrder::Xyz,
         scale: Vector3::new(0.5, 0.3, 0.8),
+        pivot: Vector3::default(),
         color: Color::new(0.0, 0.5, 1.0, 1.0), // Blue
+        emission: 2.0 + 4.0 * music.bass,
+        visible: true,
         parent: None,
-        keyframes: vec![], // We'll handle keyframes by generating objects per frame or updating them
+        keyframes: vec![],
     });
 
     // Head
@@ -76,38 +336,168 @@ pub fn calculate_walk_cycle(frame: i32, _total_frames: i32) -> Vec<Object> {
         name: "Head".to_string(),
         object_type: "CUBE".to_string(),
         location: Vector3::new(0.0, 0.0, 1.0), // Relative to Torso
-        rotation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: pose.head,
+        rotation_order: EulerOrder::Xyz,
         scale: Vector3::new(0.4, 0.4, 0.4),
+        pivot: Vector3::default(),
         color: Color::new(1.0, 0.8, 0.6, 1.0), // Skin tone-ish
+        emission: 0.0,
+        visible: true,
         parent: Some("Torso".to_string()),
         keyframes: vec![],
     });
 
-    // Limbs helper
-    let create_limb = |name: &str, parent: &str, x: f32, z: f32, rot_x: f32, color: Color| -> Object {
+    // Limbs pulse on the kick, same decay as the synth
+    let kick_env = kick_envelope(music.beat);
+    let pulse = 1.0 + 0.15 * kick_env;
+
+    // A limb segment hanging from its joint at `location`. Only the end
+    // segments pulse, since children inherit their parent's animated scale.
+    let segment = |name: String, parent: String, location: Vector3, rotation: Vector3, length: f32, end: bool| {
+        let pulse = if end { pulse } else { 1.0 };
         Object {
-            name: name.to_string(),
+            name,
             object_type: "CUBE".to_string(),
-            location: Vector3::new(x, 0.0, z),
-            rotation: Vector3::new(rot_x, 0.0, 0.0),
-            scale: Vector3::new(0.15, 0.15, 0.6),
-            color,
-            parent: Some(parent.to_string()),
+            location,
+            rotation,
+            rotation_order: EulerOrder::Xyz,
+            scale: Vector3::new(0.15 * pulse, 0.15 * pulse, length),
+            pivot: Vector3::new(0.0, 0.0, length * 0.5),
+            color: Color::new(0.0, 0.5, 1.0, 1.0),
+            emission: 2.0 + 4.0 * kick_env,
+            visible: true,
+            parent: Some(parent),
             keyframes: vec![],
         }
     };
 
-    let limb_color = Color::new(0.0, 0.5, 1.0, 1.0);
+    // Foot targets are relative to the root, the joints to the torso
+    let to_torso = objects[0].local_matrix().inverse_rigid();
+    let x_axis = Vector3::new(1.0, 0.0, 0.0);
+    for (i, (side, suffix)) in [(1.0, "L"), (-1.0, "R")].into_iter().enumerate() {
+        let mirror = |v: Vector3| Vector3::new(v.x * side, v.y, v.z);
 
-    // Arms (Swing opposite to legs)
-    let arm_swing = phase.cos() * 0.5;
-    objects.push(create_limb("Arm.L", "Torso", 0.6, 0.3, arm_swing, limb_color));
-    objects.push(create_limb("Arm.R", "Torso", -0.6, 0.3, -arm_swing, limb_color));
+        // Arms: swung by the gait, elbows bent backwards to shorten the reach
+        let swing = Mat4::from_quat(Quat::from_euler(pose.arms[i], EulerOrder::Xyz));
+        let hand = swing.transform_vector(Vector3::new(0.0, 0.0, -pose.reach * (ARM.upper + ARM.lower)));
+        let arm = ARM.solve(hand, swing.transform_vector(x_axis), swing.transform_vector(Vector3::new(0.0, 1.0, 0.0)));
+        // The hand is straight down the swing, so the aim is the swing itself
+        let upper_arm = pose.arms[i] + Vector3::new(arm.lift, 0.0, 0.0);
+        let (upper, fore) = (format!("UpperArm.{}", suffix), format!("Forearm.{}", suffix));
+        objects.push(segment(upper.clone(), "Torso".to_string(), mirror(SHOULDER), upper_arm, ARM.upper, false));
+        objects.push(segment(
+            fore,
+            upper,
+            Vector3::new(0.0, 0.0, -ARM.upper),
+            Vector3::new(arm.bend, 0.0, 0.0),
+            ARM.lower,
+            true,
+        ));
 
-    // Legs (Swing)
-    let leg_swing = phase.sin() * 0.6;
-    objects.push(create_limb("Leg.L", "Torso", 0.3, -0.8, -leg_swing, limb_color));
-    objects.push(create_limb("Leg.R", "Torso", -0.3, -0.8, leg_swing, limb_color));
+        // Legs: reach for the feet, knees bent forwards
+        let foot = to_torso.transform_point(pose.feet[i]) - mirror(HIP);
+        let leg = LEG.solve(foot, x_axis, Vector3::new(0.0, -1.0, 0.0));
+        let (thigh, shin) = (format!("Thigh.{}", suffix), format!("Shin.{}", suffix));
+        objects.push(segment(
+            thigh.clone(),
+            "Torso".to_string(),
+            mirror(HIP),
+            leg.upper().to_euler(EulerOrder::Xyz),
+            LEG.upper,
+            false,
+        ));
+        objects.push(segment(
+            shin,
+            thigh,
+            Vector3::new(0.0, 0.0, -LEG.upper),
+            Vector3::new(leg.bend, 0.0, 0.0),
+            LEG.lower,
+            true,
+        ));
+    }
 
     objects
 }
+
+/// A synthetic scene of `count` independently animated cubes on a grid, used
+/// to benchmark scene setup.
+pub fn benchmark_scene(frame: i32, count: usize) -> Vec<Object> {
+    let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+
+    (0..count)
+        .map(|i| {
+            let (col, row) = ((i % columns) as f32, (i / columns) as f32);
+            // Spread frequencies so the curves don't all fit to the same keys
+            let speed = 2.0 * PI / (40.0 + (i % 17) as f32 * 5.0);
+            let phase = frame as f32 * speed + i as f32 * 0.37;
+
+            Object {
+                name: format!("Bench.{:05}", i),
+                object_type: "CUBE".to_string(),
+                location: Vector3::new(col * 1.5, row * 1.5, 1.0 + phase.sin() * 0.5),
+                rotation: Vector3::new(0.0, 0.0, phase),
+                rotation_order: EulerOrder::Xyz,
+                scale: Vector3::new(0.5, 0.5, 0.5),
+                pivot: Vector3::default(),
+                color: Color::new(0.0, 0.5, 1.0, 1.0),
+                emission: 2.0,
+                visible: true,
+                parent: None,
+                keyframes: vec![],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn transform_graph_rejects_duplicate_names() {
+        let err = TransformGraph::new([("a", None), ("b", Some("a")), ("a", None)]).unwrap_err();
+        assert_eq!(err, TransformError::DuplicateName("a".to_string()));
+    }
+
+    #[test]
+    fn transform_graph_rejects_missing_parents() {
+        let err = TransformGraph::new([("a", None), ("b", Some("c"))]).unwrap_err();
+        assert_eq!(err, TransformError::MissingParent { object: "b".to_string(), parent: "c".to_string() });
+    }
+
+    #[test]
+    fn transform_graph_rejects_cycles() {
+        let nodes = [("root", None), ("a", Some("c")), ("b", Some("a")), ("c", Some("b"))];
+        let err = TransformGraph::new(nodes).unwrap_err();
+        match err {
+            TransformError::Cycle(names) => {
+                assert_eq!(names.len(), 4);
+                assert_eq!(names.first(), names.last());
+            }
+            e => panic!("expected a cycle, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn world_matrices_compose_parent_chains() {
+        // Children listed before their parents, to exercise the ordering
+        let graph = TransformGraph::new([("hand", Some("arm")), ("arm", Some("body")), ("body", None)]).unwrap();
+        let unit = Vector3::new(1.0, 1.0, 1.0);
+        let quarter_turn = Quat::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), FRAC_PI_2);
+        let local = [
+            Mat4::from_trs(Vector3::new(0.0, 0.0, 1.0), Quat::IDENTITY, unit),
+            Mat4::from_trs(Vector3::new(1.0, 0.0, 0.0), quarter_turn, unit),
+            Mat4::from_trs(Vector3::new(10.0, 0.0, 0.0), Quat::IDENTITY, unit),
+        ];
+        let world = graph.world_matrices(&local);
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 1e-5;
+        assert!(close(world[2].translation(), Vector3::new(10.0, 0.0, 0.0)));
+        assert!(close(world[1].translation(), Vector3::new(11.0, 0.0, 0.0)));
+        assert!(close(world[0].translation(), Vector3::new(11.0, 0.0, 1.0)));
+        // The arm's quarter turn about Z carries the hand's X axis onto Y
+        assert!(close(world[0].transform_vector(Vector3::new(1.0, 0.0, 0.0)), Vector3::new(0.0, 1.0, 0.0)));
+        assert_eq!(graph.parent(0), Some(1));
+        assert_eq!(graph.parent(2), None);
+    }
+}
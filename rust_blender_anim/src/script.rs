@@ -0,0 +1,392 @@
+//! Generation of the Python scripts executed by Blender.
+
+use std::path::Path;
+
+use crate::config::{Config, Rig};
+
+/// Builds the scene setup script.
+///
+/// The script creates the environment, the animated objects and camera
+/// described by the `output.anim_data` sidecar (`objects`, a list of
+/// [`crate::ObjAnimData`], and `camera`, a [`crate::anim::CameraAnimData`]),
+/// the audio track, and saves the result as `output.blend_file` for parallel
+/// rendering. With [`Rig::Armature`] the objects become bones of one
+/// armature instead, skinning a single mesh.
+pub fn setup_script(config: &Config) -> String {
+    let mut script = String::from(
+        r#"
+import bpy
+import json
+import math
+import time
+from mathutils import Matrix
+
+# --- Setup Scene ---
+bpy.ops.object.select_all(action='DESELECT')
+bpy.ops.object.select_by_type(type='MESH')
+bpy.ops.object.delete()
+
+"#,
+    );
+    script.push_str(&format!("FRAMES = {}\n", config.animation.frames));
+    script.push_str(&format!("FPS = {}\n", config.animation.fps));
+    script.push_str(&format!("AUDIO_FILE = {}\n", py_str(config.audio.track())));
+    script.push_str(&format!("BLEND_FILE = {}\n", py_str(&config.output.blend_file)));
+    script.push_str(&format!("ANIM_DATA_FILE = {}\n", py_str(&config.output.anim_data)));
+    let rig = match config.animation.rig {
+        Rig::Objects => "OBJECTS",
+        Rig::Armature => "ARMATURE",
+    };
+    script.push_str(&format!("RIG = {}\n", py_str(rig)));
+    script.push_str("bpy.context.scene.render.fps = FPS\n");
+    script.push_str("bpy.context.scene.frame_end = FRAMES\n");
+
+    script.push_str(r#"
+with open(ANIM_DATA_FILE) as f:
+    sidecar = json.load(f)
+anim_data = sidecar['objects']
+
+# --- Materials ---
+def create_material(name, color, emission_strength=0):
+    mat = bpy.data.materials.new(name=name)
+    mat.use_nodes = True
+    nodes = mat.node_tree.nodes
+    bsdf = nodes.get("Principled BSDF")
+    bsdf.inputs['Base Color'].default_value = color
+    bsdf.inputs['Emission'].default_value = color
+    bsdf.inputs['Emission Strength'].default_value = emission_strength
+    return mat
+
+mat_dark = create_material("DarkVoid", (0.05, 0.05, 0.05, 1), 0.0)
+mat_grid = create_material("Grid", (0, 1, 0.8, 1), 5.0)
+
+# --- Environment ---
+bpy.ops.mesh.primitive_plane_add(size=100, location=(0, 0, 0))
+road = bpy.context.active_object
+road.name = "Road"
+road.scale = (0.1, 10, 1)
+road.data.materials.append(mat_dark)
+
+for i in range(-20, 20):
+    bpy.ops.mesh.primitive_cube_add(size=0.1, location=(i * 2, 0, -0.1))
+    line = bpy.context.active_object
+    line.scale = (0.5, 1000, 0.5)
+    line.data.materials.append(mat_grid)
+
+# --- Create Objects & Apply Animation ---
+# Unit-sized primitives, so an object's scale is its size in scene units
+PRIMITIVES = {
+    'CUBE': lambda: bpy.ops.mesh.primitive_cube_add(size=1),
+    'CYLINDER': lambda: bpy.ops.mesh.primitive_cylinder_add(radius=0.5, depth=1),
+    'SPHERE': lambda: bpy.ops.mesh.primitive_uv_sphere_add(radius=0.5),
+}
+
+created_objects = {}
+materials = {}
+
+for obj_data in anim_data:
+    name = obj_data['name']
+    object_type = obj_data['object_type']
+    if object_type not in PRIMITIVES:
+        raise ValueError(f"Unsupported object_type {object_type!r} for {name!r}")
+    PRIMITIVES[object_type]()
+    obj = bpy.context.active_object
+    obj.name = name
+    obj.rotation_mode = obj_data['rotation_order']
+    created_objects[name] = obj
+
+    # Bake the scale into the mesh so children don't inherit it, and move
+    # the mesh so the origin sits on the pivot (the joint it rotates about)
+    sx, sy, sz = obj_data['scale']
+    obj.data.transform(Matrix.Diagonal((sx, sy, sz, 1.0)))
+    px, py, pz = obj_data['pivot']
+    obj.data.transform(Matrix.Translation((-px, -py, -pz)))
+
+    # Material
+    materials[name] = create_material(f"{name}.Material", obj_data['color'], obj_data['emission'])
+    obj.data.materials.append(materials[name])
+
+# Keyframes: sparse F-curves fitted in Rust, written in bulk
+INTERPOLATION = {'CONSTANT': 0, 'LINEAR': 1, 'BEZIER': 2}
+HANDLE_FREE = 0
+
+def set_enum(points, attr, names, values):
+    try:
+        points.foreach_set(attr, [values[n] for n in names])
+    except (TypeError, AttributeError):
+        # Older Blender versions can't foreach_set enum properties
+        for point, name in zip(points, names):
+            setattr(point, attr, name)
+
+def animate(id_data, channels):
+    if not channels:
+        return
+    anim = id_data.animation_data or id_data.animation_data_create()
+    if anim.action is None:
+        anim.action = bpy.data.actions.new(name=f"{id_data.name}Action")
+    for channel in channels:
+        keys = channel['keys']
+        count = len(keys['interpolation'])
+        fcurve = anim.action.fcurves.new(channel['data_path'], index=channel['index'])
+        points = fcurve.keyframe_points
+        points.add(count)
+        points.foreach_set('co', keys['co'])
+        set_enum(points, 'interpolation', keys['interpolation'], INTERPOLATION)
+        # Free handles, so update() keeps the fitted ones
+        for attr in ('handle_left_type', 'handle_right_type'):
+            set_enum(points, attr, ['FREE'] * count, {'FREE': HANDLE_FREE})
+        points.foreach_set('handle_left', keys['handle_left'])
+        points.foreach_set('handle_right', keys['handle_right'])
+        fcurve.update()
+
+def bone_channel(name, channel, offset):
+    # The same F-curve on the pose bone, shifted by `offset`
+    # Names like BVH joints may hold quotes or backslashes, so they're escaped
+    keys = dict(channel['keys'])
+    for attr in ('co', 'handle_left', 'handle_right'):
+        values = list(keys[attr])
+        values[1::2] = [v - offset for v in values[1::2]]
+        keys[attr] = values
+    return dict(channel, data_path=f'pose.bones["{bpy.utils.escape_identifier(name)}"].{channel["data_path"]}', keys=keys)
+
+BONE_LENGTH = 0.25
+
+def build_armature():
+    # Rest pose, computed in Rust: every bone along +Y with no roll, so its
+    # axes match the object's parent space and the pose channels are the
+    # object channels less the rest location
+    bones = {b['name']: b for b in sidecar['bones']}
+
+    armature = bpy.data.armatures.new('Rig')
+    rig = bpy.data.objects.new('Rig', armature)
+    bpy.context.collection.objects.link(rig)
+    bpy.context.view_layer.objects.active = rig
+    bpy.ops.object.mode_set(mode='EDIT')
+    for name, b in bones.items():
+        bone = armature.edit_bones.new(name)
+        x, y, z = b['head']
+        bone.head = (x, y, z)
+        bone.tail = (x, y + BONE_LENGTH, z)
+        bone.roll = 0.0
+    for name, b in bones.items():
+        if b['parent']:
+            armature.edit_bones[name].parent = armature.edit_bones[b['parent']]
+    bpy.ops.object.mode_set(mode='OBJECT')
+
+    # One mesh, each part rigidly bound to its bone
+    bpy.ops.object.select_all(action='DESELECT')
+    for name, obj in created_objects.items():
+        obj.data.transform(Matrix.Translation(bones[name]['head']))
+        group = obj.vertex_groups.new(name=name)
+        group.add(range(len(obj.data.vertices)), 1.0, 'REPLACE')
+        obj.select_set(True)
+    body = created_objects[anim_data[0]['name']]
+    bpy.context.view_layer.objects.active = body
+    bpy.ops.object.join()
+    body.name = 'Body'
+    body.parent = rig
+    body.modifiers.new(name='Armature', type='ARMATURE').object = rig
+
+    for obj_data in anim_data:
+        name = obj_data['name']
+        rig.pose.bones[name].rotation_mode = obj_data['rotation_order']
+        channels = []
+        for channel in obj_data['channels']:
+            # Visibility isn't a bone property
+            if channel['target'] != 'object' or channel['data_path'].startswith('hide_'):
+                continue
+            offset = bones[name]['rest'][channel['index']] if channel['data_path'] == 'location' else 0.0
+            channels.append(bone_channel(name, channel, offset))
+        animate(rig, channels)
+    return rig
+
+setup_start = time.perf_counter()
+if RIG == 'ARMATURE':
+    rig = build_armature()
+else:
+    rig = None
+    for obj_data in anim_data:
+        if obj_data['parent']:
+            created_objects[obj_data['name']].parent = created_objects[obj_data['parent']]
+    for obj_data in anim_data:
+        obj = created_objects[obj_data['name']]
+        animate(obj, [c for c in obj_data['channels'] if c['target'] == 'object'])
+for obj_data in anim_data:
+    channels = obj_data['channels']
+    animate(materials[obj_data['name']].node_tree, [c for c in channels if c['target'] == 'material'])
+print(f"GHOSTRENDER_ANIMATION_SECONDS={time.perf_counter() - setup_start:.6f}")
+
+# --- Camera ---
+camera_data = bpy.data.cameras.new(name='Camera')
+camera_object = bpy.data.objects.new('Camera', camera_data)
+bpy.context.collection.objects.link(camera_object)
+bpy.context.scene.camera = camera_object
+
+# Follows the root along its path, keyed in Rust
+camera = sidecar['camera']
+if camera['target']:
+    const = camera_object.constraints.new(type='TRACK_TO')
+    if rig:
+        const.target = rig
+        const.subtarget = camera['target']
+    else:
+        const.target = created_objects[camera['target']]
+    const.track_axis = 'TRACK_NEGATIVE_Z'
+    const.up_axis = 'UP_Y'
+animate(camera_object, camera['channels'])
+
+# --- Audio ---
+if not bpy.context.scene.sequence_editor:
+    bpy.context.scene.sequence_editor_create()
+seq = bpy.context.scene.sequence_editor.sequences.new_sound(
+    name="Beat", filepath=AUDIO_FILE, channel=1, frame_start=1
+)
+
+# --- Render Settings ---
+bpy.context.scene.render.engine = 'BLENDER_EEVEE'
+bpy.context.scene.eevee.use_bloom = True
+bpy.context.scene.render.image_settings.file_format = 'FFMPEG'
+bpy.context.scene.render.ffmpeg.format = 'MPEG4'
+bpy.context.scene.render.ffmpeg.codec = 'H264'
+bpy.context.scene.render.ffmpeg.audio_codec = 'AAC'
+
+# Save the .blend file for parallel rendering
+bpy.ops.wm.save_as_mainfile(filepath=BLEND_FILE)
+"#);
+
+    script
+}
+
+/// Builds the script that joins the rendered `part_*.mp4` chunks in
+/// `chunks_dir` into the final video at `output.video`.
+pub fn concat_script(config: &Config, chunks_dir: &Path) -> String {
+    // Generate a Python script for Blender to concat the videos
+    // This is safer than relying on ffmpeg being present
+    let mut script = format!("CHUNKS_DIR = {}\n", py_str(&chunks_dir.to_string_lossy()));
+    script.push_str(
+        r#"
+import bpy
+import os
+import glob
+
+bpy.ops.wm.read_factory_settings(use_empty=True)
+if not bpy.context.scene.sequence_editor:
+    bpy.context.scene.sequence_editor_create()
+
+# Find all part files, in chunk order: part_10_* sorts before part_2_* as text
+def chunk_index(path):
+    return int(os.path.basename(path).split("_")[1])
+
+files = sorted(glob.glob(os.path.join(CHUNKS_DIR, "part_*_*.mp4")), key=chunk_index)
+
+current_frame = 0
+for f in files:
+    print(f"Adding strip: {f}")
+    # Add movie strip
+    seq = bpy.context.scene.sequence_editor.sequences.new_movie(
+        name=os.path.basename(f),
+        filepath=os.path.abspath(f),
+        channel=1,
+        frame_start=current_frame
+    )
+    # Audio
+    bpy.context.scene.sequence_editor.sequences.new_sound(
+        name=os.path.basename(f),
+        filepath=os.path.abspath(f),
+        channel=2,
+        frame_start=current_frame
+    )
+    current_frame += seq.frame_final_duration
+
+bpy.context.scene.frame_end = current_frame
+bpy.context.scene.render.image_settings.file_format = 'FFMPEG'
+bpy.context.scene.render.ffmpeg.format = 'MPEG4'
+bpy.context.scene.render.ffmpeg.codec = 'H264'
+bpy.context.scene.render.ffmpeg.audio_codec = 'AAC'
+"#,
+    );
+    script.push_str(&format!("bpy.context.scene.render.fps = {}\n", config.animation.fps));
+    script.push_str(&format!("bpy.context.scene.render.filepath = {}\n", py_str(&video_path(&config.output.video))));
+    script.push_str("bpy.ops.render.render(animation=True)\n");
+
+    script
+}
+
+/// `video` as Blender expects it: relative paths get the `//` prefix, so
+/// they're relative to the .blend file, and absolute ones are kept.
+fn video_path(video: &str) -> String {
+    if Path::new(video).is_relative() {
+        format!("//{}", video)
+    } else {
+        video.to_string()
+    }
+}
+
+/// Quotes `s` as a Python string literal, escaping quotes, backslashes and
+/// control characters.
+pub fn py_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('\'');
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concat_script_reads_the_chunks_dir_in_chunk_order() {
+        let mut config = Config::default();
+        let script = concat_script(&config, Path::new("/tmp/chunks"));
+        assert!(script.starts_with("CHUNKS_DIR = '/tmp/chunks'\n"));
+        assert!(script.contains(r#"glob.glob(os.path.join(CHUNKS_DIR, "part_*_*.mp4")), key=chunk_index)"#));
+        assert!(script.contains("filepath = '//animation_output.mp4'\n"));
+
+        config.output.video = "/videos/out.mp4".to_string();
+        assert!(concat_script(&config, Path::new("/tmp/chunks")).contains("filepath = '/videos/out.mp4'\n"));
+    }
+
+    #[test]
+    fn setup_script_fills_fcurves_in_bulk() {
+        let script = setup_script(&Config::default());
+        assert!(script.contains("points.add(count)"));
+        assert!(script.contains("points.foreach_set('co', keys['co'])"));
+        assert!(!script.contains("keyframe_insert"));
+    }
+
+    #[test]
+    fn py_str_escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(py_str("plain"), "'plain'");
+        assert_eq!(py_str(r#"say "hi""#), r#"'say "hi"'"#);
+        assert_eq!(py_str("it's"), r"'it\'s'");
+        assert_eq!(py_str(r"C:\scenes\a.blend"), r"'C:\\scenes\\a.blend'");
+        assert_eq!(py_str("a\nb\tc\rd"), r"'a\nb\tc\rd'");
+        assert_eq!(py_str("nul\0bell\u{7}del\u{7f}"), r"'nul\u0000bell\u0007del\u007f'");
+        assert_eq!(py_str("Öhm 骨 💀"), "'Öhm 骨 💀'");
+    }
+
+    #[test]
+    fn py_str_literals_compile_back_to_the_same_string() {
+        let names = ["it's", r"back\slash", "new\nline", "tab\t", "nul\0", "Öhm 骨 💀", "'''", r"\'"];
+        let appends: String = names.iter().map(|name| format!("names.append({})\n", py_str(name))).collect();
+        let check = format!("names = []\n{}print('|'.join(names), end='')\n", appends);
+        // Skipped where Python isn't installed
+        let Ok(output) = std::process::Command::new("python3").arg("-c").arg(&check).output() else {
+            return;
+        };
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), names.join("|"));
+    }
+}
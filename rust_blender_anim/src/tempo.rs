@@ -0,0 +1,210 @@
+//! The soundtrack's tempo, shared by the synth and the animation.
+//!
+//! A [`TempoMap`] converts between seconds and beats, with instantaneous
+//! tempo changes at given beats, and splits beats into bars with its
+//! [`TimeSignature`]. The synth places its hits on the map's beats and the
+//! animation reads the same map, so the two stay in sync whatever the tempo.
+//! Times are `f64` so sample positions stay exact over long tracks.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats_per_bar: u32,
+    /// The note value of one beat (4 for quarter notes).
+    pub beat_unit: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats_per_bar: 4, beat_unit: 4 }
+    }
+}
+
+/// Where a moment falls in the music.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeatPosition {
+    /// Beats since the start, fractional.
+    pub beat: f64,
+    /// Zero-based bar.
+    pub bar: u32,
+    /// Zero-based beat within the bar.
+    pub beat_in_bar: u32,
+    /// Progress through the current beat, from 0 (on the beat) to 1.
+    pub beat_phase: f32,
+    /// Progress through the current bar, from 0 to 1.
+    pub bar_phase: f32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TempoError {
+    /// Tempos must be positive and finite.
+    InvalidBpm(f64),
+    /// Tempo changes must be at strictly increasing beats after the start.
+    UnsortedChanges,
+    InvalidSignature(TimeSignature),
+}
+
+impl fmt::Display for TempoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TempoError::InvalidBpm(bpm) => write!(f, "tempo must be positive (got {} BPM)", bpm),
+            TempoError::UnsortedChanges => write!(f, "tempo changes must be at increasing beats after 0"),
+            TempoError::InvalidSignature(s) => {
+                write!(f, "invalid time signature {}/{}", s.beats_per_bar, s.beat_unit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TempoError {}
+
+/// A span of constant tempo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    beat: f64,
+    secs: f64,
+    bpm: f64,
+}
+
+/// Tempo over the course of the track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoMap {
+    /// Sorted by beat, the first one at beat 0.
+    segments: Vec<Segment>,
+    signature: TimeSignature,
+}
+
+impl Default for TempoMap {
+    /// 120 BPM in 4/4.
+    fn default() -> Self {
+        Self::constant(120.0)
+    }
+}
+
+impl TempoMap {
+    /// A steady `bpm` in 4/4.
+    pub fn constant(bpm: f64) -> Self {
+        Self { segments: vec![Segment { beat: 0.0, secs: 0.0, bpm }], signature: TimeSignature::default() }
+    }
+
+    /// Starts at `bpm` and switches to each `(beat, bpm)` change when it
+    /// reaches its beat.
+    pub fn new(bpm: f64, changes: &[(f64, f64)], signature: TimeSignature) -> Result<Self, TempoError> {
+        if signature.beats_per_bar == 0 || signature.beat_unit == 0 {
+            return Err(TempoError::InvalidSignature(signature));
+        }
+        let mut segments = vec![Segment { beat: 0.0, secs: 0.0, bpm }];
+        for &(beat, bpm) in changes {
+            let last = *segments.last().unwrap();
+            if beat.is_nan() || beat <= last.beat {
+                return Err(TempoError::UnsortedChanges);
+            }
+            segments.push(Segment { beat, secs: last.secs + (beat - last.beat) * 60.0 / last.bpm, bpm });
+        }
+        if let Some(s) = segments.iter().find(|s| !s.bpm.is_finite() || s.bpm <= 0.0) {
+            return Err(TempoError::InvalidBpm(s.bpm));
+        }
+        Ok(Self { segments, signature })
+    }
+
+    pub fn signature(&self) -> TimeSignature {
+        self.signature
+    }
+
+    /// Tempo in effect at `secs`.
+    pub fn bpm_at(&self, secs: f64) -> f64 {
+        self.segment_at_secs(secs).bpm
+    }
+
+    /// The slowest tempo anywhere in the track.
+    pub fn slowest_bpm(&self) -> f64 {
+        self.segments.iter().map(|s| s.bpm).fold(f64::INFINITY, f64::min)
+    }
+
+    /// Beats elapsed at `secs`, fractional. Negative before the start.
+    pub fn beat_at(&self, secs: f64) -> f64 {
+        let s = self.segment_at_secs(secs);
+        s.beat + (secs - s.secs) * s.bpm / 60.0
+    }
+
+    /// The time of `beat`, the inverse of [`TempoMap::beat_at`].
+    pub fn secs_at(&self, beat: f64) -> f64 {
+        let i = self.segments.partition_point(|s| s.beat <= beat).max(1) - 1;
+        let s = &self.segments[i];
+        s.secs + (beat - s.beat) * 60.0 / s.bpm
+    }
+
+    /// Beats elapsed at `frame`.
+    pub fn beat_at_frame(&self, frame: i32, fps: u32) -> f64 {
+        self.beat_at(frame as f64 / fps as f64)
+    }
+
+    /// Bar and beat at `secs`.
+    pub fn position(&self, secs: f64) -> BeatPosition {
+        let beat = self.beat_at(secs);
+        let per_bar = self.signature.beats_per_bar as f64;
+        let bars = beat / per_bar;
+        BeatPosition {
+            beat,
+            bar: bars.floor().max(0.0) as u32,
+            beat_in_bar: beat.rem_euclid(per_bar).floor() as u32,
+            beat_phase: beat.rem_euclid(1.0) as f32,
+            bar_phase: bars.rem_euclid(1.0) as f32,
+        }
+    }
+
+    /// Bar and beat at `frame`.
+    pub fn position_at_frame(&self, frame: i32, fps: u32) -> BeatPosition {
+        self.position(frame as f64 / fps as f64)
+    }
+
+    fn segment_at_secs(&self, secs: f64) -> &Segment {
+        let i = self.segments.partition_point(|s| s.secs <= secs).max(1) - 1;
+        &self.segments[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> TempoMap {
+        // 120 BPM for 8 beats (4 s), 90 BPM for 6 beats (4 s), then 180 BPM
+        TempoMap::new(120.0, &[(8.0, 90.0), (14.0, 180.0)], TimeSignature::default()).unwrap()
+    }
+
+    #[test]
+    fn beats_and_seconds_round_trip_across_tempo_changes() {
+        let tempo = map();
+        for i in 0..200 {
+            let secs = i as f64 * 0.0731;
+            let beat = tempo.beat_at(secs);
+            assert!((tempo.secs_at(beat) - secs).abs() < 1e-9, "{} s -> beat {}", secs, beat);
+        }
+        for i in 0..100 {
+            let beat = i as f64 * 0.37;
+            assert!((tempo.beat_at(tempo.secs_at(beat)) - beat).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn tempo_changes_land_on_their_beats() {
+        let tempo = map();
+        assert!((tempo.secs_at(8.0) - 4.0).abs() < 1e-9);
+        assert!((tempo.secs_at(14.0) - 8.0).abs() < 1e-9);
+        assert!((tempo.beat_at(9.0) - 17.0).abs() < 1e-9);
+        assert_eq!(tempo.bpm_at(3.9), 120.0);
+        assert_eq!(tempo.bpm_at(4.0), 90.0);
+        assert_eq!(tempo.bpm_at(8.5), 180.0);
+        assert_eq!(tempo.slowest_bpm(), 90.0);
+    }
+
+    #[test]
+    fn invalid_maps_are_rejected() {
+        let sig = TimeSignature::default();
+        assert_eq!(TempoMap::new(120.0, &[(4.0, 100.0), (4.0, 90.0)], sig), Err(TempoError::UnsortedChanges));
+        assert_eq!(TempoMap::new(120.0, &[(4.0, 0.0)], sig), Err(TempoError::InvalidBpm(0.0)));
+        assert_eq!(TempoMap::new(-1.0, &[], sig), Err(TempoError::InvalidBpm(-1.0)));
+    }
+}
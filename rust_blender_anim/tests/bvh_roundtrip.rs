@@ -0,0 +1,90 @@
+use rust_blender_anim::bvh::{Bvh, BvhError};
+use rust_blender_anim::config::Config;
+use rust_blender_anim::math::{Mat4, Quat};
+use rust_blender_anim::mocap::Clip;
+use rust_blender_anim::scene::Vector3;
+use rust_blender_anim::{ObjAnimData, Pipeline};
+
+fn export(frames: i32) -> (Bvh, Vec<ObjAnimData>) {
+    let mut config = Config::default();
+    config.animation.frames = frames;
+    let pipeline = Pipeline::new(config);
+    let anim_data = pipeline.sample_animation().unwrap().objects;
+
+    let text = Bvh::from_anim(&anim_data, frames, 60).unwrap().to_string();
+    (Bvh::parse(&text).expect("exported BVH should parse"), anim_data)
+}
+
+fn assert_close(a: Vector3, b: Vector3, what: &str) {
+    assert!((a - b).length() < 1e-3, "{}: {:?} != {:?}", what, a, b);
+}
+
+#[test]
+fn bvh_roundtrip_preserves_hierarchy() {
+    let (bvh, anim_data) = export(30);
+    assert_eq!(bvh.joints.len(), anim_data.len());
+    assert_eq!(bvh.frames.len(), 31);
+    assert!((bvh.frame_time - 1.0 / 60.0).abs() < 1e-6);
+
+    for data in &anim_data {
+        let joint = bvh.joints.iter().find(|j| j.name == data.name).unwrap();
+        let parent = joint.parent.map(|p| bvh.joints[p].name.clone());
+        assert_eq!(parent, data.parent);
+        assert_close(joint.offset, data.state_at(0.0).location, &data.name);
+    }
+    // Parents come first, as the format requires
+    for (i, joint) in bvh.joints.iter().enumerate() {
+        assert!(joint.parent.is_none_or(|p| p < i), "{} precedes its parent", joint.name);
+    }
+    assert_eq!(bvh.joints[0].name, "Torso");
+    assert_eq!(bvh.joints[0].channels.len(), 6);
+    assert!(bvh.joints.iter().skip(1).all(|j| j.channels.len() == 3));
+    let shin = bvh.joints.iter().find(|j| j.name == "Shin.L").unwrap();
+    assert_close(shin.end_site.unwrap(), Vector3::new(0.0, 0.0, -0.9), "Shin.L end site");
+}
+
+#[test]
+fn bvh_roundtrip_preserves_motion() {
+    let frames = 90;
+    let (bvh, anim_data) = export(frames);
+
+    for frame in [0, 37, frames] {
+        for data in &anim_data {
+            let joint = bvh.joints.iter().position(|j| j.name == data.name).unwrap();
+            let (location, rotation) = bvh.local_pose(frame as usize, joint);
+            let state = data.state_at(frame as f32);
+            assert_close(location, state.location, &format!("{} location at {}", data.name, frame));
+
+            // Compare the rotated axes rather than angles, which aren't unique
+            let expected = Mat4::from_quat(Quat::from_euler(state.rotation, state.rotation_order));
+            let actual = Mat4::from_quat(rotation);
+            for axis in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)] {
+                assert_close(
+                    actual.transform_vector(axis),
+                    expected.transform_vector(axis),
+                    &format!("{} rotation at {}", data.name, frame),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn bvh_parse_reports_errors() {
+    let truncated = "HIERARCHY\nROOT Hips\n{\n\tOFFSET 0 0 0\n\tCHANNELS 3 Zrotation Xrotation Yrotation\n}\n\
+                     MOTION\nFrames: 2\nFrame Time: 0.1\n1 2 3\n";
+    assert_eq!(Bvh::parse(truncated), Err(BvhError::UnexpectedEnd));
+
+    let bad_channel = "HIERARCHY\nROOT Hips\n{\n\tOFFSET 0 0 0\n\tCHANNELS 1 Wrotation\n}\n";
+    assert!(matches!(Bvh::parse(bad_channel), Err(BvhError::Syntax { line: 5, .. })));
+
+    let empty = "HIERARCHY\nROOT Hips\n{\n\tOFFSET 0 0 0\n\tCHANNELS 3 Zrotation Xrotation Yrotation\n}\n\
+                 MOTION\nFrames: 0\nFrame Time: 0.1\n";
+    assert_eq!(Bvh::parse(empty), Err(BvhError::NoFrames));
+    // Loading the clip fails instead of panicking on the missing first frame
+    let path = std::env::temp_dir().join(format!("ghostrender_empty_{}.bvh", std::process::id()));
+    std::fs::write(&path, empty).unwrap();
+    let err = Clip::load(&path, 1.0, 10, 60).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
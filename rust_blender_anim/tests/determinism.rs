@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use rust_blender_anim::config::Config;
+use rust_blender_anim::Pipeline;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ghostrender_{}_{}", std::process::id(), name))
+}
+
+/// Writes the soundtrack and the animation sidecar with `seed`, returning
+/// their bytes.
+fn generate(seed: u64, run: &str) -> (Vec<u8>, Vec<u8>) {
+    let audio = temp_path(&format!("{}.wav", run));
+    let anim_data = temp_path(&format!("{}.json", run));
+    let script = temp_path(&format!("{}.py", run));
+
+    let mut config = Config { seed, ..Config::default() };
+    config.animation.frames = 30;
+    config.audio.duration_secs = 2;
+    config.audio.file = audio.to_string_lossy().into_owned();
+    config.output.anim_data = anim_data.to_string_lossy().into_owned();
+    config.output.script = script.to_string_lossy().into_owned();
+
+    let pipeline = Pipeline::new(config);
+    pipeline.generate_audio().unwrap();
+    pipeline.generate_script(&pipeline.sample_animation().unwrap()).unwrap();
+
+    let bytes = (std::fs::read(&audio).unwrap(), std::fs::read(&anim_data).unwrap());
+    for path in [audio, anim_data, script] {
+        std::fs::remove_file(path).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn same_seed_writes_identical_outputs() {
+    let (audio, anim_data) = generate(7, "first");
+    let (audio_again, anim_data_again) = generate(7, "second");
+    assert!(audio == audio_again, "audio differs between runs with the same seed");
+    assert!(anim_data == anim_data_again, "sidecar differs between runs with the same seed");
+}
+
+#[test]
+fn different_seeds_write_different_audio() {
+    let (audio, _) = generate(7, "seed_7");
+    let (other, _) = generate(8, "seed_8");
+    assert_eq!(audio.len(), other.len());
+    assert!(audio != other, "the seed doesn't reach the audio");
+}
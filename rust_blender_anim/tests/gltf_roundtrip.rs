@@ -0,0 +1,101 @@
+use rust_blender_anim::config::Config;
+use rust_blender_anim::math::Quat;
+use rust_blender_anim::scene::{self, Vector3};
+use rust_blender_anim::{gltf as glb, Pipeline};
+
+fn export(frames: i32) -> (gltf::Gltf, Vec<rust_blender_anim::ObjAnimData>) {
+    let mut config = Config::default();
+    config.animation.frames = frames;
+    let pipeline = Pipeline::new(config);
+    let anim_data = pipeline.sample_animation().unwrap().objects;
+
+    let path = std::env::temp_dir().join(format!("ghostrender_roundtrip_{}.glb", std::process::id()));
+    glb::write_glb(&path, &anim_data, frames, 60).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    (gltf::Gltf::from_slice(&bytes).expect("exported .glb should parse"), anim_data)
+}
+
+#[test]
+fn gltf_roundtrip_preserves_hierarchy_and_materials() {
+    let (doc, _) = export(60);
+    let objects = scene::calculate_walk_cycle(0, 60);
+    assert_eq!(doc.nodes().count(), objects.len());
+
+    for obj in &objects {
+        let node = doc.nodes().find(|n| n.name() == Some(obj.name.as_str())).unwrap();
+        let parent = doc
+            .nodes()
+            .find(|p| p.children().any(|c| c.index() == node.index()))
+            .and_then(|p| p.name().map(str::to_string));
+        assert_eq!(parent, obj.parent);
+
+        let primitive = node.mesh().unwrap().primitives().next().unwrap();
+        let material = primitive.material();
+        let [r, g, b, a] = material.pbr_metallic_roughness().base_color_factor();
+        assert_eq!([r, g, b, a], [obj.color.r, obj.color.g, obj.color.b, obj.color.a]);
+
+        // Static scale is baked into the mesh (Y-up: Blender Z is glTF Y)
+        let bounds = primitive.bounding_box();
+        let size = [bounds.max[0] - bounds.min[0], bounds.max[1] - bounds.min[1], bounds.max[2] - bounds.min[2]];
+        let expected = [obj.scale.x, obj.scale.z, obj.scale.y];
+        for (s, e) in size.iter().zip(expected) {
+            assert!((s - e).abs() < 1e-5, "{}: size {:?} != {:?}", obj.name, size, expected);
+        }
+    }
+
+    let roots: Vec<_> = doc.default_scene().unwrap().nodes().filter_map(|n| n.name().map(str::to_string)).collect();
+    assert_eq!(roots, vec!["Torso".to_string()]);
+}
+
+#[test]
+fn gltf_roundtrip_preserves_animation() {
+    let frames = 90;
+    let (doc, anim_data) = export(frames);
+    let blob = doc.blob.as_deref().expect("GLB should carry a BIN chunk");
+    let animation = doc.animations().next().unwrap();
+    assert_eq!(animation.channels().count(), anim_data.len() * 3);
+
+    let torso = doc.nodes().find(|n| n.name() == Some("Torso")).unwrap();
+    for channel in animation.channels().filter(|c| c.target().node().index() == torso.index()) {
+        let reader = channel.reader(|_| Some(blob));
+        let times: Vec<f32> = reader.read_inputs().unwrap().collect();
+        assert_eq!(times.len(), frames as usize + 1);
+        assert!((times[60] - 1.0).abs() < 1e-6);
+
+        match reader.read_outputs().unwrap() {
+            gltf::animation::util::ReadOutputs::Translations(values) => {
+                let values: Vec<[f32; 3]> = values.collect();
+                for frame in [0, 45, frames] {
+                    let obj = &scene::calculate_walk_cycle(frame, frames)[0];
+                    // The default path walks straight down -Y at a constant speed
+                    let y = obj.location.y - frame as f32 * Config::default().path.speed / 60.0;
+                    let expected = [obj.location.x, obj.location.z, -y];
+                    for (v, e) in values[frame as usize].iter().zip(expected) {
+                        assert!(
+                            (v - e).abs() < 2e-3,
+                            "frame {}: {:?} != {:?}",
+                            frame,
+                            values[frame as usize],
+                            expected
+                        );
+                    }
+                }
+            }
+            gltf::animation::util::ReadOutputs::Rotations(values) => {
+                let values: Vec<[f32; 4]> = values.into_f32().collect();
+                let obj = &scene::calculate_walk_cycle(30, frames)[0];
+                let q = Quat::from_euler_xyz(Vector3::new(obj.rotation.x, obj.rotation.y, obj.rotation.z));
+                let expected = [q.x, q.z, -q.y, q.w];
+                for (v, e) in values[30].iter().zip(expected) {
+                    assert!((v - e).abs() < 2e-3, "{:?} != {:?}", values[30], expected);
+                }
+            }
+            gltf::animation::util::ReadOutputs::Scales(mut values) => {
+                assert!(values.all(|s| s == [1.0, 1.0, 1.0]));
+            }
+            _ => panic!("unexpected output type"),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use rust_blender_anim::config::Config;
+use rust_blender_anim::math::{EulerOrder, Mat4, Quat};
+use rust_blender_anim::path::PathKind;
+use rust_blender_anim::scene::{Color, Object, TransformGraph, Vector3, LEG};
+use rust_blender_anim::Pipeline;
+
+/// A root box with a fixed tilt, in `order`.
+fn tilted_box(order: EulerOrder) -> Object {
+    Object {
+        name: "Box".to_string(),
+        object_type: "CUBE".to_string(),
+        location: Vector3::new(0.0, 0.0, 1.0),
+        rotation: Vector3::new(0.4, -0.3, 0.2),
+        rotation_order: order,
+        scale: Vector3::new(1.0, 1.0, 1.0),
+        pivot: Vector3::default(),
+        color: Color::new(1.0, 1.0, 1.0, 1.0),
+        emission: 0.0,
+        visible: true,
+        parent: None,
+        keyframes: Vec::new(),
+    }
+}
+
+fn rotate(rotation: Vector3, order: EulerOrder, v: Vector3) -> Vector3 {
+    Mat4::from_quat(Quat::from_euler(rotation, order)).transform_vector(v)
+}
+
+#[test]
+fn roots_turn_with_the_path_in_their_own_rotation_order() {
+    let mut config = Config::default();
+    config.animation.frames = 240;
+    // Around a rounded square: the heading winds past +-pi
+    config.path.kind = PathKind::CatmullRom;
+    config.path.points = vec![[0.0, 0.0, 0.0], [0.0, -6.0, 0.0], [6.0, -6.0, 0.0], [6.0, 0.0, 0.0], [0.0, 0.0, 0.0]];
+    let pipeline = Pipeline::new(config);
+    let motion = pipeline.root_motion(&pipeline.music().unwrap()).unwrap();
+
+    for order in [EulerOrder::Xyz, EulerOrder::Zyx, EulerOrder::Yxz] {
+        let rest = tilted_box(order);
+        let anim_data = pipeline.sample_with(&motion, |_, _| vec![tilted_box(order)]);
+        let mut previous = None;
+        for frame in 0..=240 {
+            let rotation = anim_data[0].state_at(frame as f32).rotation;
+            // The rest tilt, then turned about the world Z axis by the heading
+            let heading = Quat::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), motion.at(frame).heading);
+            for axis in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)] {
+                let expected = Mat4::from_quat(heading).transform_vector(rotate(rest.rotation, order, axis));
+                let actual = rotate(rotation, order, axis);
+                assert!((actual - expected).length() < 0.01, "{:?} frame {}: {:?}", order, frame, rotation);
+            }
+            // No jumps where the angles would wrap
+            if let Some(previous) = previous {
+                assert!((rotation - previous).length() < 0.5, "{:?} frame {}", order, frame);
+            }
+            previous = Some(rotation);
+        }
+    }
+}
+
+#[test]
+fn the_root_stays_put_while_an_in_place_gait_plays() {
+    let text =
+        "[animation]\nframes = 120\n[[gaits]]\nkind = \"walk\"\n[[gaits]]\nkind = \"idle\"\nstart = 60\nblend = 0";
+    let pipeline = Pipeline::new(Config::from_toml(text).unwrap());
+    let motion = pipeline.root_motion(&pipeline.music().unwrap()).unwrap();
+    assert!(motion.at(59).distance > 0.0);
+    assert_eq!(motion.at(61).distance, motion.at(120).distance);
+}
+
+#[test]
+fn planted_feet_stay_fixed_in_world_space() {
+    let mut config = Config::default();
+    config.animation.frames = 180;
+    let anim_data = Pipeline::new(config).sample_animation().unwrap().objects;
+    let graph = TransformGraph::new(anim_data.iter().map(|d| (d.name.as_str(), d.parent.as_deref()))).unwrap();
+    let shin = anim_data.iter().position(|d| d.name == "Shin.L").unwrap();
+
+    // The tip of the shin, where the foot target is
+    let feet: Vec<Vector3> = (0..=180)
+        .map(|frame| {
+            let local: Vec<Mat4> = anim_data.iter().map(|d| d.state_at(frame as f32).local_matrix()).collect();
+            graph.world_matrices(&local)[shin].transform_point(Vector3::new(0.0, 0.0, -LEG.lower))
+        })
+        .collect();
+
+    let mut planted = 0;
+    for pair in feet.windows(2) {
+        if pair[0].z < 0.01 && pair[1].z < 0.01 {
+            assert!((pair[1] - pair[0]).length() < 0.01, "the planted foot slid: {:?}", pair);
+            planted += 1;
+        }
+    }
+    // Planted for most of each step, lifted in between
+    assert!(planted > 90, "planted for {} frames", planted);
+    assert!(feet.iter().any(|foot| foot.z > 0.1));
+}
@@ -0,0 +1,98 @@
+use serde_json::Value;
+
+use rust_blender_anim::config::{Config, Rig};
+use rust_blender_anim::{scene, Pipeline};
+
+/// Writes the sidecar for a short animation with `rig` and reads it back.
+fn sidecar(name: &str, rig: Rig) -> Value {
+    let dir = std::env::temp_dir();
+    let mut config = Config::default();
+    config.animation.frames = 20;
+    config.animation.rig = rig;
+    config.output.anim_data =
+        dir.join(format!("ghostrender_{}_{}.json", name, std::process::id())).display().to_string();
+    config.output.script = dir.join(format!("ghostrender_{}_{}.py", name, std::process::id())).display().to_string();
+
+    let pipeline = Pipeline::new(config);
+    pipeline.generate_script(&pipeline.sample_animation().unwrap()).unwrap();
+    let json = std::fs::read_to_string(&pipeline.config.output.anim_data).unwrap();
+    std::fs::remove_file(&pipeline.config.output.anim_data).unwrap();
+    std::fs::remove_file(&pipeline.config.output.script).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+fn object<'a>(sidecar: &'a Value, name: &str) -> &'a Value {
+    sidecar["objects"].as_array().unwrap().iter().find(|o| o["name"] == name).unwrap()
+}
+
+fn floats(value: &Value) -> Vec<f32> {
+    value.as_array().unwrap().iter().map(|v| v.as_f64().unwrap() as f32).collect()
+}
+
+#[test]
+fn sidecar_carries_each_objects_type_scale_and_color() {
+    let sidecar = sidecar("static", Rig::Objects);
+    for obj in scene::calculate_walk_cycle(0, 20) {
+        let data = object(&sidecar, &obj.name);
+        assert_eq!(data["object_type"], obj.object_type.as_str(), "{}", obj.name);
+        assert_eq!(floats(&data["scale"]), [obj.scale.x, obj.scale.y, obj.scale.z], "{}", obj.name);
+        assert_eq!(floats(&data["color"]), [obj.color.r, obj.color.g, obj.color.b, obj.color.a], "{}", obj.name);
+        assert_eq!(data["emission"].as_f64().unwrap() as f32, obj.emission, "{}", obj.name);
+    }
+    // The objects differ, so the values aren't just defaults
+    assert_ne!(object(&sidecar, "Torso")["scale"], object(&sidecar, "Head")["scale"]);
+    assert_ne!(object(&sidecar, "Torso")["color"], object(&sidecar, "Head")["color"]);
+}
+
+#[test]
+fn scale_color_emission_and_visibility_are_animated() {
+    let mut config = Config::default();
+    config.animation.frames = 40;
+    let anim_data = Pipeline::new(config).sample_animation().unwrap().objects;
+    let arm = anim_data.iter().find(|d| d.name == "Forearm.L").unwrap();
+    let channel = |data_path: &str, index: usize| {
+        &arm.channels.iter().find(|c| c.data_path.ends_with(data_path) && c.index == index).unwrap().keys
+    };
+
+    // The limbs pulse on the beat, in size and glow, within the fitting tolerance
+    let tolerance = Config::default().animation.tolerance + 1e-4;
+    for frame in 0..=40 {
+        let obj = scene::calculate_walk_cycle(frame, 40).into_iter().find(|o| o.name == "Forearm.L").unwrap();
+        let f = frame as f32;
+        assert!((channel("scale", 0).evaluate(f) * arm.scale[0] - obj.scale.x).abs() < tolerance, "frame {}", frame);
+        assert!(
+            (channel("inputs[\"Emission Strength\"].default_value", 0).evaluate(f) - obj.emission).abs() < tolerance
+        );
+    }
+    // The color and visibility never change, so they're left to the static values
+    assert!(!arm.channels.iter().any(|c| c.data_path.contains("Base Color") || c.data_path.starts_with("hide_")));
+    assert_eq!(arm.color, [0.0, 0.5, 1.0, 1.0]);
+}
+
+#[test]
+fn armature_bones_follow_the_object_hierarchy() {
+    assert!(sidecar("objects", Rig::Objects).get("bones").is_none());
+
+    let sidecar = sidecar("armature", Rig::Armature);
+    let bones = sidecar["bones"].as_array().unwrap();
+    let bone = |name: &str| bones.iter().find(|b| b["name"] == name).unwrap();
+    let objects = scene::calculate_walk_cycle(0, 20);
+    assert_eq!(bones.len(), objects.len());
+    for obj in &objects {
+        let b = bone(&obj.name);
+        assert_eq!(b["parent"].as_str(), obj.parent.as_deref(), "{}", obj.name);
+
+        // The head sits at the rest locations summed up the chain
+        let mut head = floats(&b["rest"]);
+        let mut parent = obj.parent.clone();
+        while let Some(name) = parent {
+            for (h, r) in head.iter_mut().zip(floats(&bone(&name)["rest"])) {
+                *h += r;
+            }
+            parent = objects.iter().find(|o| o.name == name).unwrap().parent.clone();
+        }
+        assert_eq!(floats(&b["head"]), head, "{}", obj.name);
+    }
+    assert!(bone("Torso")["parent"].is_null());
+    assert_eq!(bone("Shin.L")["parent"], "Thigh.L");
+}
@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use rust_blender_anim::audio::wav::{SampleFormat, Wav, WavSpec, WavWriter};
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(bytes[pos..pos + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+/// The chunks after the RIFF header, as `(id, offset of the body, size)`.
+fn chunks(bytes: &[u8]) -> Vec<(String, usize, u32)> {
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = String::from_utf8_lossy(&bytes[pos..pos + 4]).into_owned();
+        let size = u32_at(bytes, pos + 4);
+        chunks.push((id.clone(), pos + 8, size));
+        if id == "data" {
+            break;
+        }
+        pos += 8 + size as usize + size as usize % 2;
+    }
+    chunks
+}
+
+fn write(spec: WavSpec, samples: &[f32], rf64: bool) -> Vec<u8> {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+    if rf64 {
+        writer = writer.always_rf64();
+    }
+    writer.write_samples(samples).unwrap();
+    writer.finalize().unwrap().into_inner()
+}
+
+/// A ramp through the full range, interleaved over `channels`.
+fn ramp(frames: usize, channels: u16) -> Vec<f32> {
+    let n = frames * usize::from(channels);
+    (0..n).map(|i| i as f32 / n as f32 * 2.0 - 1.0).collect()
+}
+
+#[test]
+fn wav_writer_headers_parse_back() {
+    for format in [SampleFormat::I16, SampleFormat::I24, SampleFormat::F32] {
+        for channels in [1, 2, 6] {
+            let spec = WavSpec { channels, sample_rate: 48000, format };
+            let samples = ramp(1001, channels);
+            let bytes = write(spec, &samples, false);
+            let what = format!("{:?} x{}", format, channels);
+
+            assert_eq!(&bytes[0..4], b"RIFF", "{}", what);
+            assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8, "{}: RIFF size", what);
+            assert_eq!(&bytes[8..12], b"WAVE", "{}", what);
+
+            let chunks = chunks(&bytes);
+            let (_, fmt, fmt_size) = chunks.iter().find(|c| c.0 == "fmt ").unwrap().clone();
+            let extensible = format != SampleFormat::I16 || channels > 2;
+            assert_eq!(fmt_size, if extensible { 40 } else { 16 }, "{}: fmt size", what);
+            let tag = if extensible { u16_at(&bytes, fmt + 24) } else { u16_at(&bytes, fmt) };
+            assert_eq!(tag, if format == SampleFormat::F32 { 3 } else { 1 }, "{}: format tag", what);
+            assert_eq!(u16_at(&bytes, fmt + 2), channels, "{}: channels", what);
+            assert_eq!(u32_at(&bytes, fmt + 4), 48000, "{}: sample rate", what);
+            let block_align = channels * format.bits() / 8;
+            assert_eq!(u32_at(&bytes, fmt + 8), 48000 * u32::from(block_align), "{}: byte rate", what);
+            assert_eq!(u16_at(&bytes, fmt + 12), block_align, "{}: block align", what);
+            assert_eq!(u16_at(&bytes, fmt + 14), format.bits(), "{}: bits", what);
+
+            let (_, data, data_size) = chunks.last().unwrap().clone();
+            assert_eq!(chunks.last().unwrap().0, "data", "{}", what);
+            assert_eq!(data_size as usize, samples.len() * usize::from(format.bits() / 8), "{}: data size", what);
+            assert_eq!(bytes.len(), data + data_size as usize + data_size as usize % 2, "{}: file size", what);
+
+            let wav = Wav::decode(&bytes).unwrap();
+            assert_eq!((wav.sample_rate, wav.channels), (48000, channels), "{}", what);
+            assert_eq!(wav.samples.len(), samples.len(), "{}", what);
+            for (a, b) in wav.samples.iter().zip(&samples) {
+                assert!((a - b).abs() < 1e-4, "{}: {} != {}", what, a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn wav_writer_pads_odd_data() {
+    let spec = WavSpec { channels: 1, sample_rate: 44100, format: SampleFormat::I24 };
+    let bytes = write(spec, &[0.5], false);
+    let (_, data, data_size) = chunks(&bytes).last().unwrap().clone();
+    assert_eq!(data_size, 3);
+    assert_eq!(bytes.len(), data + 4);
+    assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+    assert_eq!(Wav::decode(&bytes).unwrap().samples.len(), 1);
+}
+
+#[test]
+fn wav_writer_rf64_header_parses_back() {
+    let spec = WavSpec { channels: 2, sample_rate: 44100, format: SampleFormat::I16 };
+    let samples = ramp(500, 2);
+    let bytes = write(spec, &samples, true);
+
+    assert_eq!(&bytes[0..4], b"RF64");
+    assert_eq!(u32_at(&bytes, 4), u32::MAX);
+    let chunks = chunks(&bytes);
+    assert_eq!(chunks[0].0, "ds64");
+    let ds64 = chunks[0].1;
+    assert_eq!(u64_at(&bytes, ds64) as usize, bytes.len() - 8, "RIFF size");
+    assert_eq!(u64_at(&bytes, ds64 + 8) as usize, samples.len() * 2, "data size");
+    assert_eq!(u64_at(&bytes, ds64 + 16), 500, "sample count");
+    assert_eq!(chunks.last().unwrap().0, "data");
+    assert_eq!(chunks.last().unwrap().2, u32::MAX);
+
+    let wav = Wav::decode(&bytes).unwrap();
+    assert_eq!(wav.samples.len(), samples.len());
+
+    // Small files keep the space for the ds64 chunk as JUNK
+    let bytes = write(spec, &samples, false);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(&bytes[12..16], b"JUNK");
+}